@@ -0,0 +1,300 @@
+use crate::api::file_handling::{read_file, save_file, uncompressed_path};
+use crate::api::format::Format;
+use crate::api::m64_handling::{ascii_to_string, M64Error, M64File};
+use crate::api::movie_text;
+use crate::api::stats::stats;
+use crate::api::timecode::{duration, format_time};
+use anyhow::Result;
+use serde::Serialize;
+use std::any::Any;
+use std::fs;
+use std::io;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Something to do to every movie of a batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Job {
+    /// Clears the names of the plugins the movie was recorded with
+    StripPlugins,
+    /// Brings the header in line with the inputs, see [`M64File::fix_header`]
+    FixHeader,
+    /// Writes the text form of the movie next to it, as NAME.txt
+    ToText,
+    /// Reports the length, rerecords and A presses of the movie
+    Stats,
+    /// Reports the problems found by [`M64File::validate`]
+    Validate,
+}
+
+impl Job {
+    pub const ALL: [Job; 5] = [Job::StripPlugins, Job::FixHeader, Job::ToText, Job::Stats, Job::Validate];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Job::StripPlugins => "strip-plugins",
+            Job::FixHeader => "fix-header",
+            Job::ToText => "to-text",
+            Job::Stats => "stats",
+            Job::Validate => "validate",
+        }
+    }
+}
+
+impl FromStr for Job {
+    type Err = M64Error;
+
+    fn from_str(s: &str) -> Result<Job, M64Error> {
+        Job::ALL.into_iter()
+            .find(|job| job.name().eq_ignore_ascii_case(s))
+            .ok_or(M64Error { message: format!("Unknown job \"{}\"", s) })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Unchanged,
+    Changed,
+    // Read fine but has problems, from a validation
+    Invalid,
+    // Couldn't be read or written
+    Failed,
+}
+
+impl Status {
+    pub fn name(self, dry_run: bool) -> &'static str {
+        match self {
+            Status::Unchanged => "ok",
+            Status::Changed if dry_run => "would change",
+            Status::Changed => "changed",
+            Status::Invalid => "invalid",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+/// What a job did to one movie.
+#[derive(Clone, Debug, Serialize)]
+pub struct Outcome {
+    pub path: PathBuf,
+    pub status: Status,
+    // The changes made, problems found or statistics, one line each
+    pub notes: Vec<String>,
+}
+
+/// Every movie under `root`, including compressed ones, in name order. A file is taken
+/// as is, whatever its name.
+pub fn find_movies(root: &Path) -> io::Result<Vec<PathBuf>> {
+    if !root.is_dir() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+    let mut movies = Vec::new();
+    let mut entries: Vec<PathBuf> = fs::read_dir(root)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            movies.extend(find_movies(&path)?);
+        } else if Format::from_path(&path) == Some(Format::M64) {
+            movies.push(path);
+        }
+    }
+    Ok(movies)
+}
+
+/// Runs `job` on every movie in `paths`, spread over as many threads as there are cores. The
+/// outcomes are in the same order as `paths`. With `dry_run` nothing is written, and the
+/// outcomes tell what would have changed.
+pub fn run(job: Job, paths: &[PathBuf], dry_run: bool) -> Vec<Outcome> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(paths.len());
+    let next = AtomicUsize::new(0);
+    let mut outcomes: Vec<(usize, Outcome)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| scope.spawn(|| {
+                let mut outcomes = Vec::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(i) else { break };
+                    // A movie that trips a bug fails on its own instead of ending the batch
+                    let outcome = panic::catch_unwind(|| run_one(job, path, dry_run))
+                        .unwrap_or_else(|payload| failed(path, panic_message(payload.as_ref())));
+                    outcomes.push((i, outcome));
+                }
+                outcomes
+            }))
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    });
+    outcomes.sort_by_key(|(i, _)| *i);
+    outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+fn run_one(job: Job, path: &Path, dry_run: bool) -> Outcome {
+    match apply(job, path, dry_run) {
+        Ok((status, notes)) => Outcome { path: path.to_path_buf(), status, notes },
+        Err(e) => failed(path, e.to_string()),
+    }
+}
+
+fn failed(path: &Path, message: String) -> Outcome {
+    Outcome { path: path.to_path_buf(), status: Status::Failed, notes: vec![message] }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let message = payload.downcast_ref::<&str>().copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown error");
+    format!("Crashed: {}", message)
+}
+
+fn apply(job: Job, path: &Path, dry_run: bool) -> Result<(Status, Vec<String>)> {
+    let data = read_file(path)?;
+    let format = Format::of(path, &data);
+    let mut movie = format.decode(&data)?.movie;
+    let notes = match job {
+        Job::StripPlugins => strip_plugins(&mut movie),
+        Job::FixHeader => movie.fix_header(),
+        Job::ToText => {
+            let text_path = uncompressed_path(path).with_extension(Format::Text.extension());
+            let text = movie_text::encode(&movie).into_bytes();
+            if fs::read(&text_path).is_ok_and(|existing| existing == text) {
+                return Ok((Status::Unchanged, Vec::new()));
+            }
+            if !dry_run {
+                save_file(&text_path, &text)?;
+            }
+            return Ok((Status::Changed, vec![format!("Text in {}", text_path.display())]));
+        }
+        Job::Stats => return Ok((Status::Unchanged, describe_stats(&movie))),
+        Job::Validate => {
+            let problems = movie.validate();
+            let status = if problems.is_empty() { Status::Unchanged } else { Status::Invalid };
+            return Ok((status, problems));
+        }
+    };
+    if notes.is_empty() {
+        return Ok((Status::Unchanged, notes));
+    }
+    if !dry_run {
+        // Saved in the format it was read in; save_file keeps the compression
+        save_file(path, &format.encode(&movie)?.movie)?;
+    }
+    Ok((Status::Changed, notes))
+}
+
+fn strip_plugins(movie: &mut M64File) -> Vec<String> {
+    let empty = [0_u8.as_ascii().unwrap(); 64];
+    [
+        ("video", &mut movie.video_plugin),
+        ("sound", &mut movie.sound_plugin),
+        ("input", &mut movie.input_plugin),
+        ("RSP", &mut movie.rsp_plugin),
+    ]
+    .into_iter()
+    .filter(|(_, plugin)| **plugin != empty)
+    .map(|(kind, plugin)| {
+        let name = ascii_to_string(plugin);
+        *plugin = empty;
+        format!("Cleared the {} plugin \"{}\"", kind, name)
+    })
+    .collect()
+}
+
+fn describe_stats(movie: &M64File) -> Vec<String> {
+    let controllers = M64File::active_controllers(movie.controller_flags).unwrap_or_default();
+    let mut notes = vec![format!(
+        "{} frames, {}, {} rerecords",
+        movie.frames(),
+        format_time(duration(movie)),
+        movie.rerecord_count
+    )];
+    for i in controllers {
+        let inputs = &movie.inputs[i];
+        notes.push(format!("Controller {}: {} A presses", i + 1, stats(inputs, 0..inputs.len()).a_presses));
+    }
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::file_handling::TempDir;
+    use crate::api::m64_handling::{string_to_ascii, test_movie};
+    use std::slice;
+
+    // A movie with a plugin name and a wrong sample count, saved as movie.m64
+    fn write_movie(directory: &TempDir) -> (PathBuf, M64File) {
+        let mut movie = test_movie(30, 0b0001);
+        movie.video_plugin = string_to_ascii("video").unwrap();
+        movie.num_samples = 5;
+        let path = directory.join("movie.m64");
+        fs::write(&path, movie.to_bytes().unwrap()).unwrap();
+        (path, movie)
+    }
+
+    #[test]
+    fn movies_are_found_in_subdirectories() {
+        let directory = TempDir::new("batch");
+        fs::create_dir_all(directory.join("nested")).unwrap();
+        for name in ["nested/b.m64", "a.m64.gz", "notes.txt", "c.bk2"] {
+            fs::write(directory.join(name), b"").unwrap();
+        }
+        let movies = find_movies(directory.path()).unwrap();
+        assert_eq!(movies, [directory.join("a.m64.gz"), directory.join("nested/b.m64")]);
+        assert_eq!(find_movies(&directory.join("notes.txt")).unwrap(), [directory.join("notes.txt")]);
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() {
+        let directory = TempDir::new("batch");
+        let (path, movie) = write_movie(&directory);
+        let outcome = &run(Job::StripPlugins, slice::from_ref(&path), true)[0];
+        assert_eq!(outcome.status, Status::Changed);
+        assert_eq!(outcome.notes, ["Cleared the video plugin \"video\""]);
+        assert_eq!(fs::read(&path).unwrap(), movie.to_bytes().unwrap());
+        assert_eq!(run(Job::ToText, slice::from_ref(&path), true)[0].status, Status::Changed);
+        assert!(!path.with_extension("txt").exists());
+    }
+
+    #[test]
+    fn changes_are_saved() {
+        let directory = TempDir::new("batch");
+        let (path, _) = write_movie(&directory);
+        assert_eq!(run(Job::Validate, slice::from_ref(&path), false)[0].status, Status::Invalid);
+        assert_eq!(run(Job::FixHeader, slice::from_ref(&path), false)[0].status, Status::Changed);
+        assert_eq!(run(Job::FixHeader, slice::from_ref(&path), false)[0].status, Status::Unchanged);
+        assert_eq!(run(Job::Validate, slice::from_ref(&path), false)[0].status, Status::Unchanged);
+    }
+
+    #[test]
+    fn stats_are_reported() {
+        let directory = TempDir::new("batch");
+        let (path, _) = write_movie(&directory);
+        let outcome = &run(Job::Stats, &[path], false)[0];
+        assert_eq!(outcome.status, Status::Unchanged);
+        assert!(outcome.notes[0].starts_with("30 frames"), "{:?}", outcome.notes);
+        assert_eq!(outcome.notes[1], "Controller 1: 10 A presses");
+    }
+
+    #[test]
+    fn unreadable_movies_fail_on_their_own() {
+        let directory = TempDir::new("batch");
+        let (path, _) = write_movie(&directory);
+        let broken = directory.join("broken.m64");
+        fs::write(&broken, b"M64").unwrap();
+        let outcomes = run(Job::StripPlugins, &[broken, directory.join("missing.m64"), path], true);
+        let statuses: Vec<Status> = outcomes.iter().map(|outcome| outcome.status).collect();
+        assert_eq!(statuses, [Status::Failed, Status::Failed, Status::Changed]);
+    }
+
+    #[test]
+    fn jobs_are_parsed_by_name() {
+        assert_eq!("Fix-Header".parse::<Job>().unwrap(), Job::FixHeader);
+        assert!("fix".parse::<Job>().is_err());
+    }
+}
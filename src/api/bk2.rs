@@ -0,0 +1,386 @@
+use crate::api::m64_handling::{ascii_to_string, string_to_ascii, Button, Input, M64Error, M64File};
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::io::{Cursor, Read, Write};
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+// BizHawk's N64 buttons with their log mnemonics, in log order
+const BUTTONS: [(&str, char, Button); 14] = [
+    ("DPad U", 'U', Button::DUp),
+    ("DPad D", 'D', Button::DDown),
+    ("DPad L", 'L', Button::DLeft),
+    ("DPad R", 'R', Button::DRight),
+    ("Start", 'S', Button::Start),
+    ("Z", 'Z', Button::Z),
+    ("B", 'B', Button::B),
+    ("A", 'A', Button::A),
+    ("C Up", 'u', Button::CUp),
+    ("C Down", 'd', Button::CDown),
+    ("C Left", 'l', Button::CLeft),
+    ("C Right", 'r', Button::CRight),
+    ("L", 'L', Button::L),
+    ("R", 'R', Button::R),
+];
+
+// PakType values of BizHawk's N64 sync settings
+const NO_PAK: u64 = 1;
+const MEMORY_PAK: u64 = 2;
+const RUMBLE_PAK: u64 = 3;
+
+// Movie start types of the m64 header
+const START_FROM_SNAPSHOT: u16 = 1;
+const START_FROM_POWER_ON: u16 = 2;
+
+/// A converted movie, with what couldn't be carried over.
+pub struct Conversion<T> {
+    pub movie: T,
+    pub warnings: Vec<String>,
+}
+
+/// One field of the input log: a controller's analog values and buttons, or the console buttons.
+enum Field {
+    Console,
+    Controller(usize, Vec<String>),
+}
+
+fn error(message: String) -> anyhow::Error {
+    M64Error { message }.into()
+}
+
+fn read_entry(archive: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<Option<String>> {
+    let mut entry = match archive.by_name(name) {
+        Ok(entry) => entry,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut text = String::new();
+    entry.read_to_string(&mut text)?;
+    Ok(Some(text))
+}
+
+/// Splits a LogKey line into its fields, e.g. "#Reset|Power|#P1 X Axis|P1 Y Axis|...".
+fn parse_log_key(key: &str) -> Result<Vec<Field>> {
+    key.split('#')
+        .filter(|group| !group.is_empty())
+        .map(|group| {
+            let names: Vec<String> = group.split('|').filter(|name| !name.is_empty()).map(str::to_string).collect();
+            let port = names.first()
+                .and_then(|name| name.strip_prefix('P'))
+                .and_then(|name| name.split(' ').next())
+                .and_then(|number| number.parse::<usize>().ok());
+            match port {
+                Some(port @ 1..=4) => Ok(Field::Controller(port - 1, names)),
+                Some(port) => Err(error(format!("Controller {} is not supported, Mupen has 4 ports", port))),
+                None => Ok(Field::Console),
+            }
+        })
+        .collect()
+}
+
+fn default_log_key(ports: &[usize]) -> String {
+    let mut key = "#Reset|Power|".to_string();
+    for port in ports {
+        key.push_str(&format!("#P{0} X Axis|P{0} Y Axis|", port + 1));
+        for (name, _, _) in BUTTONS {
+            key.push_str(&format!("P{} {}|", port + 1, name));
+        }
+    }
+    key
+}
+
+/// Converts a .bk2 archive to a movie.
+pub fn import(data: &[u8]) -> Result<Conversion<M64File>> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let mut warnings = Vec::new();
+    let header_text = read_entry(&mut archive, "Header.txt")?.ok_or_else(|| error("Header.txt is missing".to_string()))?;
+    let log = read_entry(&mut archive, "Input Log.txt")?.ok_or_else(|| error("Input Log.txt is missing".to_string()))?;
+
+    let mut movie = M64File::new();
+    movie.movie_start_type = START_FROM_POWER_ON;
+    movie.vi_per_second = 60;
+    for line in header_text.lines() {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "Platform" if value != "N64" => return Err(error(format!("Movie is for {}, not N64", value))),
+            "Author" => set_text(&mut movie.author, value, "Author", &mut warnings),
+            // The title from BizHawk's game database, not the name in the ROM header
+            "GameName" => warnings.push(format!("Game \"{}\" can't be converted to a ROM name; set the ROM in the header tab", value)),
+            "rerecordCount" => movie.rerecord_count = value.trim().parse().unwrap_or(0),
+            "PAL" if value.eq_ignore_ascii_case("true") => movie.vi_per_second = 50,
+            "StartsFromSavestate" | "StartsFromSaveRam" if value.eq_ignore_ascii_case("true") => {
+                warnings.push("Movie starts from a savestate, which can't be converted; it will start from power-on".to_string());
+            }
+            "SHA1" => warnings.push(format!("ROM SHA1 {} can't be converted to a CRC; set the ROM in the header tab", value)),
+            _ => {}
+        }
+    }
+    if let Some(comments) = read_entry(&mut archive, "Comments.txt")? {
+        set_text(&mut movie.movie_desc, comments.trim(), "Comments", &mut warnings);
+    }
+    if read_entry(&mut archive, "Subtitles.txt")?.is_some_and(|subtitles| !subtitles.trim().is_empty()) {
+        warnings.push("Subtitles are dropped".to_string());
+    }
+
+    let mut fields = parse_log_key(&default_log_key(&[0]))?;
+    let mut frame = 0;
+    let mut resets = 0;
+    for line in log.lines() {
+        if let Some(key) = line.strip_prefix("LogKey:") {
+            fields = parse_log_key(key)?;
+            continue;
+        }
+        if !line.starts_with('|') {
+            continue;
+        }
+        let values: Vec<&str> = line.trim_matches('|').split('|').collect();
+        if values.len() != fields.len() {
+            return Err(error(format!("Input log line {} has {} fields, expected {}", frame + 1, values.len(), fields.len())));
+        }
+        for (field, value) in fields.iter().zip(values) {
+            match field {
+                Field::Console => resets += value.chars().filter(|&c| c != '.').count().min(1),
+                Field::Controller(port, names) => {
+                    movie.inputs[*port].push(parse_controller(names, value, frame, &mut warnings)?);
+                }
+            }
+        }
+        frame += 1;
+    }
+    if resets > 0 {
+        warnings.push(format!("{} frames press Reset or Power, which m64 can't record", resets));
+    }
+
+    for field in &fields {
+        if let Field::Controller(port, _) = field {
+            movie.controller_flags |= 1 << port;
+        }
+    }
+    if let Some(settings) = read_entry(&mut archive, "SyncSettings.json")? {
+        read_sync_settings(&mut movie, &settings, &mut warnings);
+    }
+    movie.controller_count = (movie.controller_flags & 0xF).count_ones() as u8;
+    movie.num_samples = frame as u32;
+    // BizHawk logs every frame the emulator runs, so each line is a VI
+    movie.vi_count = frame as u32;
+    warnings.push("Each BizHawk frame becomes one input sample; games that don't poll the controller every VI will desync".to_string());
+    Ok(Conversion { movie, warnings })
+}
+
+fn parse_controller(names: &[String], value: &str, frame: usize, warnings: &mut Vec<String>) -> Result<Input> {
+    let mut input = Input::new();
+    let mut parts: Vec<&str> = value.split(',').collect();
+    let buttons = parts.pop().unwrap_or("");
+    let analog_names = names.iter().filter(|name| name.ends_with("Axis"));
+    for (name, part) in analog_names.zip(parts) {
+        let raw: i64 = part.trim().parse().map_err(|_| error(format!("Frame {}: invalid {} \"{}\"", frame, name, part.trim())))?;
+        let clamped = raw.clamp(i8::MIN as i64, i8::MAX as i64) as i8;
+        if clamped as i64 != raw {
+            warnings.push(format!("Frame {}: {} {} is out of range and was clamped", frame, name, raw));
+        }
+        if name.ends_with("X Axis") {
+            input.x = clamped;
+        } else {
+            input.y = clamped;
+        }
+    }
+    let button_names = names.iter().filter(|name| !name.ends_with("Axis"));
+    for (name, c) in button_names.zip(buttons.chars()) {
+        let button = BUTTONS.iter().find(|(suffix, _, _)| name.split_once(' ').is_some_and(|(_, n)| n == *suffix));
+        match button {
+            Some(&(_, _, button)) => input.set(button, c != '.'),
+            None if c != '.' => warnings.push(format!("Frame {}: {} has no m64 equivalent", frame, name)),
+            None => {}
+        }
+    }
+    Ok(input)
+}
+
+fn read_sync_settings(movie: &mut M64File, settings: &str, warnings: &mut Vec<String>) {
+    let Ok(settings) = serde_json::from_str::<Value>(settings) else {
+        warnings.push("SyncSettings.json could not be read".to_string());
+        return;
+    };
+    let Some(controllers) = settings.pointer("/o/Controllers").and_then(Value::as_array) else { return };
+    for (port, controller) in controllers.iter().take(4).enumerate() {
+        if controller["IsConnected"].as_bool() == Some(false) {
+            continue;
+        }
+        // Only the ports in the input log have frames to go with them
+        if movie.controller_flags & (1 << port) == 0 {
+            warnings.push(format!("Controller {} is connected but has no inputs in the log, so it is left out", port + 1));
+            continue;
+        }
+        match controller["PakType"].as_u64() {
+            Some(MEMORY_PAK) => movie.controller_flags |= 1 << (port + 4),
+            Some(RUMBLE_PAK) => movie.controller_flags |= 1 << (port + 8),
+            Some(NO_PAK) | None => {}
+            Some(_) => warnings.push(format!("Controller {} has a pak m64 can't record", port + 1)),
+        }
+    }
+}
+
+fn set_text<const N: usize>(field: &mut [std::ascii::Char; N], value: &str, name: &str, warnings: &mut Vec<String>) {
+    let ascii: String = value.chars().map(|c| if c.is_ascii() { c } else { '?' }).collect();
+    if ascii != value {
+        warnings.push(format!("{} has characters that aren't ASCII", name));
+    }
+    let truncated = &ascii[..ascii.len().min(N)];
+    if truncated.len() < ascii.len() {
+        warnings.push(format!("{} is longer than {} characters and was cut short", name, N));
+    }
+    *field = string_to_ascii(truncated).unwrap();
+}
+
+/// Converts a movie to a .bk2 archive.
+pub fn export(movie: &M64File) -> Result<Conversion<Vec<u8>>> {
+    let mut warnings = Vec::new();
+    let ports = M64File::active_controllers(movie.controller_flags)?;
+    let frames = ports.iter().map(|&port| movie.inputs[port].len()).max().unwrap_or(0);
+
+    let mut header = String::new();
+    header.push_str("MovieVersion BizHawk v2.0.0\n");
+    header.push_str(&format!("Author {}\n", ascii_to_string(&movie.author)));
+    header.push_str("Platform N64\n");
+    header.push_str(&format!("GameName {}\n", ascii_to_string(&movie.internal_name).trim_end()));
+    header.push_str("Core Mupen64Plus\n");
+    header.push_str(&format!("rerecordCount {}\n", movie.rerecord_count));
+    if movie.vi_per_second == 50 {
+        header.push_str("PAL True\n");
+    }
+    warnings.push(format!("ROM CRC {:08X} has no BizHawk equivalent; BizHawk will ask to confirm the ROM", movie.crc32));
+    if movie.movie_start_type == START_FROM_SNAPSHOT {
+        warnings.push("Movie starts from a savestate, which isn't included; it will start from power-on".to_string());
+    }
+    let plugins = [&movie.video_plugin, &movie.sound_plugin, &movie.input_plugin, &movie.rsp_plugin]
+        .map(|plugin| ascii_to_string(plugin));
+    if plugins.iter().any(|plugin| !plugin.is_empty()) {
+        warnings.push(format!("Plugins are set by BizHawk's core settings, not the movie: {}", plugins.join(", ")));
+    }
+    if movie.vi_count != movie.num_samples {
+        warnings.push(format!(
+            "Movie has {} VIs for {} samples; BizHawk logs every VI, so it will desync unless the game polls every VI",
+            movie.vi_count, movie.num_samples
+        ));
+    }
+
+    let mut log = format!("[Input]\nLogKey:{}\n", default_log_key(&ports));
+    for frame in 0..frames {
+        log.push_str("|..|");
+        for &port in &ports {
+            let input = movie.inputs[port].get(frame).cloned().unwrap_or_else(Input::new);
+            log.push_str(&format!("{:5},{:5},", input.x, input.y));
+            for (_, mnemonic, button) in BUTTONS {
+                log.push(if input.pressed(button) { mnemonic } else { '.' });
+            }
+            log.push('|');
+        }
+        log.push('\n');
+    }
+    log.push_str("[/Input]\n");
+
+    let controllers: Vec<Value> = (0..4)
+        .map(|port| {
+            let pak = if movie.controller_flags & (1 << (port + 4)) != 0 {
+                MEMORY_PAK
+            } else if movie.controller_flags & (1 << (port + 8)) != 0 {
+                RUMBLE_PAK
+            } else {
+                NO_PAK
+            };
+            json!({ "IsConnected": ports.contains(&port), "PakType": pak })
+        })
+        .collect();
+    let sync_settings = json!({
+        "o": {
+            "$type": "BizHawk.Emulation.Cores.Nintendo.N64.N64SyncSettings, BizHawk.Emulation.Cores",
+            "Controllers": controllers,
+        }
+    });
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let entries = [
+        ("Header.txt", header),
+        ("Input Log.txt", log),
+        ("SyncSettings.json", sync_settings.to_string()),
+        ("Comments.txt", ascii_to_string(&movie.movie_desc)),
+        ("Subtitles.txt", String::new()),
+    ];
+    for (name, text) in entries {
+        writer.start_file(name, SimpleFileOptions::default())?;
+        writer.write_all(text.as_bytes())?;
+    }
+    Ok(Conversion { movie: writer.finish()?.into_inner(), warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::m64_handling::test_movie;
+
+    fn archive(entries: &[(&str, String)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, text) in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(text.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn inputs_round_trip() {
+        let mut movie = test_movie(30, 0b0101 | 0x10 | 0x400);
+        movie.inputs[2][4].c_left = true;
+        movie.inputs[0][7].x = -128;
+        movie.inputs[0][8].y = 127;
+        let imported = import(&export(&movie).unwrap().movie).unwrap().movie;
+        assert_eq!(imported.inputs, movie.inputs);
+        assert!(imported.validate().is_empty(), "{:?}", imported.validate());
+    }
+
+    #[test]
+    fn ports_and_paks_round_trip() {
+        let movie = test_movie(5, 0b0101 | 0x10 | 0x400);
+        let imported = import(&export(&movie).unwrap().movie).unwrap().movie;
+        assert_eq!(imported.controller_flags, movie.controller_flags);
+        assert_eq!(imported.controller_count, 2);
+    }
+
+    #[test]
+    fn header_fields_round_trip() {
+        let mut movie = test_movie(30, 0b0001);
+        movie.vi_count = 30;
+        movie.rerecord_count = 12;
+        movie.author = string_to_ascii("someone").unwrap();
+        let imported = import(&export(&movie).unwrap().movie).unwrap().movie;
+        assert_eq!((imported.num_samples, imported.vi_count, imported.rerecord_count), (30, 30, 12));
+        assert_eq!(ascii_to_string(&imported.author), "someone");
+    }
+
+    #[test]
+    fn game_name_is_not_the_rom_name() {
+        let mut movie = test_movie(5, 0b0001);
+        movie.internal_name = string_to_ascii("SUPER MARIO 64").unwrap();
+        let conversion = import(&export(&movie).unwrap().movie).unwrap();
+        assert_eq!(ascii_to_string(&conversion.movie.internal_name), "");
+        assert!(conversion.warnings.iter().any(|warning| warning.contains("SUPER MARIO 64")));
+    }
+
+    #[test]
+    fn connected_ports_without_inputs_are_left_out() {
+        let settings = json!({ "o": { "Controllers": [
+            { "IsConnected": true, "PakType": MEMORY_PAK },
+            { "IsConnected": true, "PakType": RUMBLE_PAK },
+        ] } });
+        let log = format!("[Input]\nLogKey:{}\n|..|    1,   -1,..............|\n[/Input]\n", default_log_key(&[0]));
+        let data = archive(&[
+            ("Header.txt", "Platform N64\n".to_string()),
+            ("Input Log.txt", log),
+            ("SyncSettings.json", settings.to_string()),
+        ]);
+        let conversion = import(&data).unwrap();
+        assert_eq!(conversion.movie.controller_flags, 0b0001 | 0x10);
+        assert_eq!(conversion.movie.controller_count, 1);
+        assert!(conversion.movie.to_bytes().is_ok());
+    }
+}
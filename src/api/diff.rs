@@ -0,0 +1,289 @@
+use crate::api::m64_handling::{Button, Input, M64File};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+/// Alignment is skipped for inputs that differ by more than this many inserted and deleted
+/// frames, as its memory use grows with the square of the distance. The frames are then
+/// compared one to one instead.
+const MAX_EDIT_DISTANCE: usize = 2048;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HeaderDifference {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ChangeKind {
+    Changed,
+    Inserted,
+    Deleted,
+}
+
+/// A run of frames that differ between the two movies.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Hunk {
+    pub kind: ChangeKind,
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+    // For changed frames, the buttons that differ and whether the stick differs
+    pub buttons: Vec<Button>,
+    pub stick: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ControllerDiff {
+    pub controller: usize,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MovieDiff {
+    pub header: Vec<HeaderDifference>,
+    pub controllers: Vec<ControllerDiff>,
+}
+
+impl MovieDiff {
+    pub fn is_empty(&self) -> bool {
+        self.header.is_empty() && self.controllers.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+pub fn diff(old: &M64File, new: &M64File) -> MovieDiff {
+    let header = old.header_fields().into_iter()
+        .zip(new.header_fields())
+        .filter(|((_, old), (_, new))| old != new)
+        .map(|((field, old), (_, new))| HeaderDifference { field, old, new })
+        .collect();
+    let controllers = (0..4)
+        .map(|controller| ControllerDiff { controller, hunks: diff_inputs(&old.inputs[controller], &new.inputs[controller]) })
+        .filter(|diff| !diff.hunks.is_empty())
+        .collect();
+    MovieDiff { header, controllers }
+}
+
+/// Aligns the two input sequences and returns the runs of frames that differ.
+pub fn diff_inputs(old: &[Input], new: &[Input]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    let ops = align(old_middle, new_middle).unwrap_or_else(|| positional(old_middle, new_middle));
+
+    let mut hunks = Vec::new();
+    let (mut x, mut y) = (prefix, prefix);
+    let mut current: Option<(usize, usize)> = None;
+    for op in ops.into_iter().chain([Op::Equal]) {
+        match op {
+            Op::Equal => {
+                if let Some((start_x, start_y)) = current.take() {
+                    hunks.push(hunk(old, new, start_x..x, start_y..y));
+                }
+                x += 1;
+                y += 1;
+            }
+            Op::Delete => {
+                current.get_or_insert((x, y));
+                x += 1;
+            }
+            Op::Insert => {
+                current.get_or_insert((x, y));
+                y += 1;
+            }
+        }
+    }
+    hunks
+}
+
+fn hunk(old: &[Input], new: &[Input], old_range: Range<usize>, new_range: Range<usize>) -> Hunk {
+    let kind = match (old_range.is_empty(), new_range.is_empty()) {
+        (true, _) => ChangeKind::Inserted,
+        (_, true) => ChangeKind::Deleted,
+        _ => ChangeKind::Changed,
+    };
+    let pairs = || old[old_range.clone()].iter().zip(&new[new_range.clone()]);
+    let buttons = Button::ALL.into_iter()
+        .filter(|&button| pairs().any(|(a, b)| a.pressed(button) != b.pressed(button)))
+        .collect();
+    let stick = pairs().any(|(a, b)| a.x != b.x || a.y != b.y);
+    Hunk { kind, old: old_range, new: new_range, buttons, stick }
+}
+
+/// Myers' diff, returning None if the sequences are too far apart.
+fn align(old: &[Input], new: &[Input]) -> Option<Vec<Op>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max_d = (old.len() + new.len()).min(MAX_EDIT_DISTANCE) as isize;
+    let offset = max_d + 1;
+    let mut v = vec![0_isize; 2 * offset as usize + 1];
+    // The furthest x reached on each diagonal before each step, k in -d-1..=d+1
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    for d in 0..=max_d {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) { v[i + 1] } else { v[i - 1] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+        }
+    }
+    None
+}
+
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<Op> {
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            ops.push(Op::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            ops.push(if x == prev_x { Op::Insert } else { Op::Delete });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.reverse();
+    ops
+}
+
+fn positional(old: &[Input], new: &[Input]) -> Vec<Op> {
+    let mut ops = Vec::new();
+    for (a, b) in old.iter().zip(new) {
+        if a == b {
+            ops.push(Op::Equal);
+        } else {
+            ops.extend([Op::Delete, Op::Insert]);
+        }
+    }
+    ops.extend(std::iter::repeat_n(Op::Delete, old.len().saturating_sub(new.len())));
+    ops.extend(std::iter::repeat_n(Op::Insert, new.len().saturating_sub(old.len())));
+    ops
+}
+
+pub fn format_frames(range: &Range<usize>) -> String {
+    match range.len() {
+        0 | 1 => format!("frame {}", range.start),
+        _ => format!("frames {}-{}", range.start, range.end - 1),
+    }
+}
+
+impl Display for Hunk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ChangeKind::Inserted => write!(f, "{} inserted before old frame {}", format_frames(&self.new), self.old.start),
+            ChangeKind::Deleted => write!(f, "{} deleted", format_frames(&self.old)),
+            ChangeKind::Changed => {
+                write!(f, "{} changed", format_frames(&self.old))?;
+                if self.old != self.new {
+                    write!(f, " (now {})", format_frames(&self.new))?;
+                }
+                let mut changes: Vec<&str> = self.buttons.iter().map(Button::label).collect();
+                if self.stick {
+                    changes.push("stick");
+                }
+                if !changes.is_empty() {
+                    write!(f, ": {}", changes.join(" "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for MovieDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "Movies are identical");
+        }
+        if !self.header.is_empty() {
+            writeln!(f, "Header:")?;
+            for difference in &self.header {
+                writeln!(f, "  {}: {:?} -> {:?}", difference.field, difference.old, difference.new)?;
+            }
+        }
+        for controller in &self.controllers {
+            writeln!(f, "Controller {}:", controller.controller + 1)?;
+            for hunk in &controller.hunks {
+                writeln!(f, "  {}", hunk)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::m64_handling::test_movie;
+
+    #[test]
+    fn identical_movies() {
+        let movie = test_movie(50, 0b0011);
+        assert!(diff(&movie, &movie).is_empty());
+    }
+
+    #[test]
+    fn inserted_frames_are_aligned() {
+        let old = test_movie(50, 0b0001).inputs[0].clone();
+        let mut new = old.clone();
+        new.splice(10..10, vec![Input::new(); 3]);
+        new.remove(40);
+        let hunks = diff_inputs(&old, &new);
+        assert_eq!(hunks.len(), 2);
+        assert_eq!((hunks[0].kind, hunks[0].old.clone(), hunks[0].new.clone()), (ChangeKind::Inserted, 10..10, 10..13));
+        assert_eq!((hunks[1].kind, hunks[1].old.clone(), hunks[1].new.clone()), (ChangeKind::Deleted, 37..38, 40..40));
+    }
+
+    #[test]
+    fn changed_frames_list_buttons() {
+        let old = test_movie(50, 0b0001);
+        let mut new = old.clone();
+        new.inputs[0][20].r_trig = true;
+        new.inputs[0][21].x = 5;
+        new.rerecord_count = 3;
+        let diff = diff(&old, &new);
+        assert_eq!(diff.header, vec![HeaderDifference { field: "rerecord_count", old: "0".to_string(), new: "3".to_string() }]);
+        let hunks = &diff.controllers[0].hunks;
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].kind, hunks[0].old.clone()), (ChangeKind::Changed, 20..22));
+        assert_eq!(hunks[0].buttons, vec![Button::R]);
+        assert!(hunks[0].stick);
+    }
+
+    #[test]
+    fn distant_inputs_are_compared_one_to_one() {
+        let old: Vec<Input> = (0..3000).map(|i| Input { x: 1, y: (i % 2) as i8, ..Input::new() }).collect();
+        let new: Vec<Input> = (0..3000).map(|i| Input { x: 2, y: (i % 2) as i8, ..Input::new() }).collect();
+        let hunks = diff_inputs(&old, &new);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].kind, hunks[0].old.clone(), hunks[0].new.clone()), (ChangeKind::Changed, 0..3000, 0..3000));
+    }
+}
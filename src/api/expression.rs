@@ -0,0 +1,249 @@
+use crate::api::m64_handling::{Button, Input};
+use crate::api::search::{syntax_error, Parser, Predicate, Token, Value};
+use crate::api::stick;
+use anyhow::Result;
+use bitvec::prelude::BitVec;
+use std::str::FromStr;
+
+// Text syntax, e.g. "where A && |stick| > 64 set B = true, x = -x":
+//   expression: ("where" predicate)? ("set" assignment ("," assignment)*)?
+//   assignment: button "=" ("true" | "false" | predicate) | ("x" | "y") "=" sum
+//   sum:        product (("+" | "-") product)*
+//   product:    factor (("*" | "/") factor)*
+//   factor:     "-" factor | "(" sum ")" | number | value
+// Predicates are as in search. Every right-hand side is evaluated on the frame before
+// any assignment is made, so "set x = y, y = x" swaps the axes.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Arithmetic {
+    Number(f64),
+    Value(Value),
+    Negate(Box<Arithmetic>),
+    Add(Box<Arithmetic>, Box<Arithmetic>),
+    Subtract(Box<Arithmetic>, Box<Arithmetic>),
+    Multiply(Box<Arithmetic>, Box<Arithmetic>),
+    Divide(Box<Arithmetic>, Box<Arithmetic>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Assignment {
+    Button(Button, Predicate),
+    // Buttons set to a constant, which predicates have no syntax for
+    ButtonConstant(Button, bool),
+    Stick(Axis, Arithmetic),
+}
+
+/// A filter on frames and the assignments to make to the matching ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    pub filter: Option<Predicate>,
+    pub assignments: Vec<Assignment>,
+}
+
+impl Arithmetic {
+    pub fn of(&self, input: &Input) -> f64 {
+        match self {
+            Arithmetic::Number(number) => *number,
+            Arithmetic::Value(value) => value.of(input),
+            Arithmetic::Negate(a) => -a.of(input),
+            Arithmetic::Add(a, b) => a.of(input) + b.of(input),
+            Arithmetic::Subtract(a, b) => a.of(input) - b.of(input),
+            Arithmetic::Multiply(a, b) => a.of(input) * b.of(input),
+            Arithmetic::Divide(a, b) => a.of(input) / b.of(input),
+        }
+    }
+}
+
+impl Expression {
+    /// Which frames of `inputs` the expression applies to.
+    pub fn matches(&self, inputs: &[Input]) -> BitVec {
+        match &self.filter {
+            Some(predicate) => predicate.evaluate(inputs),
+            None => BitVec::repeat(true, inputs.len()),
+        }
+    }
+
+    /// Makes the assignments to the matching frames, returning how many frames changed.
+    pub fn apply(&self, inputs: &mut [Input]) -> usize {
+        let matches = self.matches(inputs);
+        // Predicates are evaluated over the whole range up front, as held() looks at neighbouring frames
+        let buttons: Vec<Option<BitVec>> = self.assignments.iter()
+            .map(|assignment| match assignment {
+                Assignment::Button(_, predicate) => Some(predicate.evaluate(inputs)),
+                _ => None,
+            })
+            .collect();
+
+        let mut changed = 0;
+        for frame in matches.iter_ones() {
+            let old = inputs[frame].clone();
+            let mut new = old.clone();
+            for (assignment, values) in self.assignments.iter().zip(&buttons) {
+                match assignment {
+                    Assignment::Button(button, _) => new.set(*button, values.as_ref().unwrap()[frame]),
+                    Assignment::ButtonConstant(button, value) => new.set(*button, *value),
+                    Assignment::Stick(Axis::X, value) => new.x = stick::to_raw(value.of(&old)),
+                    Assignment::Stick(Axis::Y, value) => new.y = stick::to_raw(value.of(&old)),
+                }
+            }
+            if new != old {
+                inputs[frame] = new;
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+impl Parser {
+    fn assignment(&mut self) -> Result<Assignment> {
+        let column = self.column();
+        let Some(Token::Name(name)) = self.peek().cloned() else {
+            return Err(self.error("expected a button, \"x\" or \"y\""));
+        };
+        self.next();
+        if name.eq_ignore_ascii_case("x") || name.eq_ignore_ascii_case("y") {
+            let axis = if name.eq_ignore_ascii_case("x") { Axis::X } else { Axis::Y };
+            self.expect("=")?;
+            return Ok(Assignment::Stick(axis, self.sum()?));
+        }
+        let button = name.parse::<Button>()
+            .map_err(|_| syntax_error(column, &format!("cannot assign to \"{}\"", name)))?;
+        self.expect("=")?;
+        if self.eat_name("true") {
+            Ok(Assignment::ButtonConstant(button, true))
+        } else if self.eat_name("false") {
+            Ok(Assignment::ButtonConstant(button, false))
+        } else {
+            Ok(Assignment::Button(button, self.predicate()?))
+        }
+    }
+
+    fn sum(&mut self) -> Result<Arithmetic> {
+        let mut sum = self.product()?;
+        loop {
+            if self.eat("+") {
+                sum = Arithmetic::Add(Box::new(sum), Box::new(self.product()?));
+            } else if self.eat("-") {
+                sum = Arithmetic::Subtract(Box::new(sum), Box::new(self.product()?));
+            } else {
+                return Ok(sum);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Arithmetic> {
+        let mut product = self.factor()?;
+        loop {
+            if self.eat("*") {
+                product = Arithmetic::Multiply(Box::new(product), Box::new(self.factor()?));
+            } else if self.eat("/") {
+                product = Arithmetic::Divide(Box::new(product), Box::new(self.factor()?));
+            } else {
+                return Ok(product);
+            }
+        }
+    }
+
+    fn factor(&mut self) -> Result<Arithmetic> {
+        if self.eat("-") {
+            return Ok(Arithmetic::Negate(Box::new(self.factor()?)));
+        }
+        if self.eat("(") {
+            let sum = self.sum()?;
+            self.expect(")")?;
+            return Ok(sum);
+        }
+        if self.eat("|stick|") {
+            return Ok(Arithmetic::Value(Value::Magnitude));
+        }
+        let value = match self.peek() {
+            Some(Token::Number(number)) => Arithmetic::Number(*number),
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("x") => Arithmetic::Value(Value::X),
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("y") => Arithmetic::Value(Value::Y),
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("angle") => Arithmetic::Value(Value::Angle),
+            _ => return Err(self.error("expected a number, \"x\", \"y\", \"|stick|\" or \"angle\"")),
+        };
+        self.next();
+        Ok(value)
+    }
+}
+
+impl FromStr for Expression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Expression> {
+        let mut parser = Parser::new(s)?;
+        if parser.at_end() {
+            return Err(parser.error("expected \"where\" or \"set\""));
+        }
+        let filter = if parser.eat_name("where") { Some(parser.predicate()?) } else { None };
+        let mut assignments = Vec::new();
+        if parser.eat_name("set") {
+            assignments.push(parser.assignment()?);
+            while parser.eat(",") {
+                assignments.push(parser.assignment()?);
+            }
+        }
+        if !parser.at_end() {
+            return Err(match (&filter, assignments.is_empty()) {
+                (None, true) => parser.error("expected \"where\" or \"set\""),
+                (Some(_), true) => parser.error("expected \"&&\", \"||\" or \"set\""),
+                _ => parser.error("expected \",\" or end of input"),
+            });
+        }
+        Ok(Expression { filter, assignments })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stick_x(expression: &str, x: i8, y: i8) -> i8 {
+        let mut inputs = vec![Input { x, y, ..Input::new() }];
+        expression.parse::<Expression>().unwrap().apply(&mut inputs);
+        inputs[0].x
+    }
+
+    #[test]
+    fn arithmetic_precedence() {
+        assert_eq!(stick_x("set x = 2 + 3 * 4", 0, 0), 14);
+        assert_eq!(stick_x("set x = (2 + 3) * 4", 0, 0), 20);
+        assert_eq!(stick_x("set x = 10 - 4 - 3", 0, 0), 3);
+        assert_eq!(stick_x("set x = 24 / 4 / 2", 0, 0), 3);
+        assert_eq!(stick_x("set x = -2 * 3 - 1", 0, 0), -7);
+        assert_eq!(stick_x("set x = --y", 0, 9), 9);
+    }
+
+    #[test]
+    fn division_by_zero_saturates() {
+        assert_eq!(stick_x("set x = y / 0", 0, 5), 127);
+        assert_eq!(stick_x("set x = y / 0", 0, -5), -128);
+        // 0 / 0 is not a number, which leaves the stick centred
+        assert_eq!(stick_x("set x = y / 0", 50, 0), 0);
+    }
+
+    #[test]
+    fn assignments_read_the_frame_before_the_change() {
+        let mut inputs = vec![Input { x: 1, y: 2, ..Input::new() }, Input { x: 3, y: 4, a_button: true, ..Input::new() }];
+        let expression = "where A set x = y, y = x, B = true".parse::<Expression>().unwrap();
+        assert_eq!(expression.apply(&mut inputs), 1);
+        assert_eq!((inputs[0].x, inputs[0].y, inputs[0].b_button), (1, 2, false));
+        assert_eq!((inputs[1].x, inputs[1].y, inputs[1].b_button), (4, 3, true));
+        assert_eq!(expression.apply(&mut inputs[..1]), 0);
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        for text in ["", "set", "set x", "set x =", "set q = 1", "where", "set x = 1 y", "set x = (1", "where A B", "set x = 1,"] {
+            assert!(text.parse::<Expression>().is_err(), "{}", text);
+        }
+    }
+}
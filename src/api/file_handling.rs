@@ -1,25 +1,263 @@
 use std::io;
-use std::fs::File;
+use std::ffi::OsString;
+use std::fs::{self, File};
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 use crate::api::m64_handling::ByteVec;
 
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+const ZSTD_LEVEL: i32 = 19;
+
 #[derive(Debug, Clone)]
 pub enum DialogError {
     DialogClosed,
     IoError(io::ErrorKind),
 }
+
+/// How a file is compressed on disk. Files are read by their magic bytes, whatever their name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn detect(data: &[u8]) -> Compression {
+        if data.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if data.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Compression for a file named `path`, from a `.gz` or `.zst` extension.
+    pub fn from_extension(path: &Path) -> Compression {
+        match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    pub fn compress(self, bytes: &[u8]) -> io::Result<ByteVec> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            Compression::Zstd => zstd::encode_all(bytes, ZSTD_LEVEL),
+        }
+    }
+
+    pub fn decompress(self, bytes: &[u8]) -> io::Result<ByteVec> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            Compression::Gzip => {
+                let mut buffer = Vec::new();
+                GzDecoder::new(bytes).read_to_end(&mut buffer)?;
+                Ok(buffer)
+            }
+            Compression::Zstd => zstd::decode_all(bytes),
+        }
+    }
+}
+
+/// Path without a `.gz` or `.zst` extension, to find the format of a compressed file by name.
+pub fn uncompressed_path(path: &Path) -> PathBuf {
+    match Compression::from_extension(path) {
+        Compression::None => path.to_path_buf(),
+        _ => path.with_extension(""),
+    }
+}
+
+/// Reads a file, decompressing it if it is compressed.
 pub fn read_file(path: &Path) -> io::Result<ByteVec> {
 
     let mut file = File::open(path)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
-    Ok(buffer)
+    match Compression::detect(&buffer) {
+        Compression::None => Ok(buffer),
+        compression => compression.decompress(&buffer),
+    }
 }
 
+/// Writes a file, compressed if its name ends in `.gz` or `.zst`, or if it replaces a file
+/// that was compressed.
 pub fn save_file(path: &Path, bytes: &ByteVec) -> io::Result<(File)> {
-    let mut file = File::create(path)?;
+    save_file_with_backups(path, bytes, 0)
+}
+
+/// Writes a file like [`save_file`], first keeping up to `backups` previous versions of it.
+///
+/// The file is written to a temporary file next to it and renamed over it once it is on
+/// disk, so a failed write leaves the previous version in place.
+pub fn save_file_with_backups(path: &Path, bytes: &ByteVec, backups: usize) -> io::Result<File> {
+    let compression = match Compression::from_extension(path) {
+        Compression::None => existing_compression(path),
+        compression => compression,
+    };
+    let temp_path = temp_path(path);
+    let file = write_synced(&temp_path, path, &compression.compress(bytes)?)
+        .and_then(|file| {
+            if backups > 0 && path.exists() {
+                rotate_backups(path, backups)?;
+            }
+            fs::rename(&temp_path, path)?;
+            Ok(file)
+        })
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temp_path);
+        })?;
+    // Make the rename itself durable. Directories can't be opened on every platform.
+    if let Some(parent) = path.parent().and_then(|parent| File::open(parent).ok()) {
+        let _ = parent.sync_all();
+    }
+    Ok(file)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+fn write_synced(temp_path: &Path, path: &Path, bytes: &[u8]) -> io::Result<File> {
+    let mut file = File::create(temp_path)?;
     file.write_all(bytes)?;
+    file.sync_all()?;
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(temp_path, metadata.permissions())?;
+    }
     Ok(file)
 }
 
+/// Where the `n`th newest backup of `path` is kept, counting from 1, e.g. `movie.m64.1.bak`.
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.bak", n));
+    path.with_file_name(name)
+}
+
+/// The file that `backup` is a backup of, e.g. `movie.m64` for `movie.m64.1.bak`.
+pub fn backup_source(backup: &Path) -> Option<PathBuf> {
+    let name = backup.file_name()?.to_str()?;
+    let (source, n) = name.strip_suffix(".bak")?.rsplit_once('.')?;
+    n.parse::<usize>().ok()?;
+    Some(backup.with_file_name(source))
+}
+
+/// The backups of `path` that exist, newest first.
+pub fn backups(path: &Path) -> Vec<PathBuf> {
+    (1..).map(|n| backup_path(path, n)).take_while(|backup| backup.exists()).collect()
+}
+
+/// Removes the backups of `path` past the newest `keep`, e.g. after keeping fewer backups.
+pub fn remove_old_backups(path: &Path, keep: usize) -> io::Result<()> {
+    for backup in backups(path).into_iter().skip(keep) {
+        fs::remove_file(backup)?;
+    }
+    Ok(())
+}
+
+// Shifts each backup one place older, dropping the oldest, and copies the file as the newest
+fn rotate_backups(path: &Path, backups: usize) -> io::Result<()> {
+    let oldest = backup_path(path, backups);
+    if oldest.exists() {
+        fs::remove_file(oldest)?;
+    }
+    for n in (1..backups).rev() {
+        let backup = backup_path(path, n);
+        if backup.exists() {
+            fs::rename(backup, backup_path(path, n + 1))?;
+        }
+    }
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+fn existing_compression(path: &Path) -> Compression {
+    let mut magic = [0; 4];
+    match File::open(path).and_then(|mut file| file.read_exact(&mut magic)) {
+        Ok(()) => Compression::detect(&magic),
+        Err(_) => Compression::None,
+    }
+}
+
+/// A directory of its own for a test, removed with everything in it when dropped.
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub(crate) fn new(name: &str) -> TempDir {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        // Tests run in parallel threads of one process, so the id alone isn't unique
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("m64-{}-{}-{}", name, std::process::id(), count));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub(crate) fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backups_name_their_source() {
+        let path = Path::new("dir/movie.txt.gz");
+        assert_eq!(backup_source(&backup_path(path, 3)).as_deref(), Some(path));
+        assert_eq!(backup_source(Path::new("movie.bak")), None);
+        assert_eq!(backup_source(Path::new("movie.m64.old.bak")), None);
+    }
+
+    #[test]
+    fn old_backups_are_removed() {
+        let directory = TempDir::new("backups");
+        let path = directory.join("movie.m64");
+        fs::write(&path, b"movie").unwrap();
+        for _ in 0..4 {
+            save_file_with_backups(&path, &b"movie".to_vec(), 4).unwrap();
+        }
+        assert_eq!(backups(&path).len(), 4);
+        remove_old_backups(&path, 2).unwrap();
+        assert_eq!(backups(&path), [backup_path(&path, 1), backup_path(&path, 2)]);
+        assert!(!backup_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn compression_round_trip() {
+        let bytes: ByteVec = (0..1000).map(|i| (i % 7) as u8).collect();
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let compressed = compression.compress(&bytes).unwrap();
+            assert_eq!(Compression::detect(&compressed), compression);
+            assert_eq!(compression.decompress(&compressed).unwrap(), bytes);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::api::bk2::{self, Conversion};
+use crate::api::file_handling::{read_file, uncompressed_path};
+use crate::api::m64_handling::{ByteVec, M64Error, M64File};
+use crate::api::{movie_text, table};
+use anyhow::Result;
+use std::path::Path;
+use std::str::FromStr;
+
+const M64_SIGNATURE: [u8; 4] = [0x4D, 0x36, 0x34, 0x1A];
+const ZIP_SIGNATURE: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+
+/// The file formats a movie can be read from and written to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    M64,
+    Bk2,
+    Text,
+    Csv,
+    Json,
+}
+
+impl Format {
+    pub const ALL: [Format; 5] = [Format::M64, Format::Bk2, Format::Text, Format::Csv, Format::Json];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::M64 => "m64",
+            Format::Bk2 => "bk2",
+            Format::Text => "text",
+            Format::Csv => "csv",
+            Format::Json => "json",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Text => "txt",
+            format => format.name(),
+        }
+    }
+
+    /// The format a file is named after, looking past a `.gz` or `.zst` extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let extension = uncompressed_path(path).extension()?.to_str()?.to_ascii_lowercase();
+        Format::ALL.into_iter().find(|format| format.extension() == extension)
+    }
+
+    /// Guesses the format from the content, for data without a name such as standard input.
+    pub fn detect(data: &[u8]) -> Format {
+        let text = std::str::from_utf8(data).map(str::trim_start);
+        if data.starts_with(&M64_SIGNATURE) {
+            Format::M64
+        } else if data.starts_with(&ZIP_SIGNATURE) {
+            Format::Bk2
+        } else if movie_text::is_movie_text(data) {
+            Format::Text
+        } else if text.is_ok_and(|text| text.starts_with('{')) {
+            Format::Json
+        } else if text.is_ok() {
+            Format::Csv
+        } else {
+            Format::M64
+        }
+    }
+
+    /// The format of `data` read from `path`. Text movies are often saved as .m64, so those
+    /// are checked by their content.
+    pub fn of(path: &Path, data: &[u8]) -> Format {
+        match Format::from_path(path) {
+            Some(Format::M64) if movie_text::is_movie_text(data) => Format::Text,
+            Some(format) => format,
+            None => Format::detect(data),
+        }
+    }
+
+    pub fn decode(self, data: &[u8]) -> Result<Conversion<M64File>> {
+        let mut warnings = Vec::new();
+        if self == Format::M64 && M64File::partial_sample_bytes(data) > 0 {
+            warnings.push("File ends partway through an input, which was dropped".to_string());
+        }
+        let movie = match self {
+            Format::M64 => M64File::from_bytes(&data.to_vec())?,
+            Format::Bk2 => return bk2::import(data),
+            Format::Text => movie_text::decode(std::str::from_utf8(data)?)?,
+            Format::Csv => table::from_csv(std::str::from_utf8(data)?)?,
+            Format::Json => table::from_json(std::str::from_utf8(data)?)?,
+        };
+        Ok(Conversion { movie, warnings })
+    }
+
+    pub fn encode(self, movie: &M64File) -> Result<Conversion<ByteVec>> {
+        let bytes = match self {
+            Format::M64 => movie.to_bytes()?,
+            Format::Bk2 => return bk2::export(movie),
+            Format::Text => movie_text::encode(movie).into_bytes(),
+            Format::Csv => table::to_csv(movie).into_bytes(),
+            Format::Json => table::to_json(movie).into_bytes(),
+        };
+        Ok(Conversion { movie: bytes, warnings: Vec::new() })
+    }
+}
+
+impl FromStr for Format {
+    type Err = M64Error;
+
+    fn from_str(s: &str) -> Result<Format, M64Error> {
+        Format::ALL.into_iter()
+            .find(|format| format.name().eq_ignore_ascii_case(s) || format.extension().eq_ignore_ascii_case(s))
+            .ok_or(M64Error { message: format!("Unknown format \"{}\"", s) })
+    }
+}
+
+/// Reads a movie in any format, decompressing it if needed.
+pub fn read_movie(path: &Path) -> Result<Conversion<M64File>> {
+    let data = read_file(path)?;
+    Format::of(path, &data).decode(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::m64_handling::test_movie;
+
+    #[test]
+    fn content_is_detected_by_its_magic() {
+        let movie = test_movie(10, 0b0001);
+        for format in Format::ALL {
+            let bytes = format.encode(&movie).unwrap().movie;
+            assert_eq!(Format::detect(&bytes), format, "{}", format.name());
+        }
+        assert_eq!(Format::detect(&[0xFF, 0xFE, 0x00]), Format::M64);
+    }
+
+    #[test]
+    fn names_are_checked_before_content() {
+        let text = movie_text::encode(&test_movie(1, 0b0001)).into_bytes();
+        assert_eq!(Format::of(Path::new("movie.m64"), &text), Format::Text);
+        assert_eq!(Format::of(Path::new("movie.csv.gz"), &text), Format::Csv);
+        assert_eq!(Format::of(Path::new("movie"), &text), Format::Text);
+        assert_eq!(Format::from_path(Path::new("MOVIE.BK2")), Some(Format::Bk2));
+        assert_eq!(Format::from_path(Path::new("movie.zip")), None);
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let movie = test_movie(10, 0b0011);
+        for format in Format::ALL {
+            let bytes = format.encode(&movie).unwrap().movie;
+            let decoded = format.decode(&bytes).unwrap().movie;
+            assert_eq!(decoded.inputs, movie.inputs, "{}", format.name());
+        }
+    }
+
+    #[test]
+    fn invalid_data_is_rejected() {
+        assert!(Format::M64.decode(b"M64\x1A").is_err());
+        assert!(Format::Text.decode(&[0xFF, 0xFE]).is_err());
+        assert!(Format::Json.decode(b"{").is_err());
+        assert!(Format::Bk2.decode(b"PK\x03\x04").is_err());
+        assert!("avi".parse::<Format>().is_err());
+        assert_eq!("TXT".parse::<Format>().unwrap(), Format::Text);
+    }
+}
@@ -0,0 +1,402 @@
+use crate::api::m64_handling::{ByteVec, Controllers, Input, M64Error, M64File};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// A reversible change to a movie.
+///
+/// Edits only store the part of the movie they touch, so the history of a long editing session
+/// costs roughly as much memory as the frames that were actually changed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Edit {
+    // The 0x400 byte header before and after the change
+    Header { old: ByteVec, new: ByteVec },
+    // Sets the header's sample count, which inserting and removing frames changes
+    SampleCount { old: u32, new: u32 },
+    // Overwrites frames of a single controller
+    Replace { controller: usize, start: usize, old: Vec<Input>, new: Vec<Input> },
+    // Inserts frames at `start`, one vector per controller, empty for inactive controllers
+    Insert { start: usize, inputs: Controllers },
+    Remove { start: usize, removed: Controllers },
+    // Exchanges two controller ports, see `M64File::swap_controllers`
+    SwapPorts(usize, usize),
+    // Several edits that are undone and redone together
+    Batch(Vec<Edit>),
+}
+
+impl Edit {
+    pub fn header(movie: &M64File, new: &M64File) -> Edit {
+        Edit::Header { old: movie.header_to_bytes(), new: new.header_to_bytes() }
+    }
+
+    /// Overwrites the frames of `controller` starting at `start`, storing only the frames that differ.
+    pub fn replace(movie: &M64File, controller: usize, start: usize, mut new: Vec<Input>) -> Edit {
+        let current = &movie.inputs[controller];
+        let end = (start + new.len()).min(current.len());
+        let mut old = current.get(start..end).unwrap_or_default().to_vec();
+        new.truncate(old.len());
+
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+        old.truncate(old.len() - suffix);
+        new.truncate(new.len() - suffix);
+        Edit::Replace { controller, start: start + prefix, old: old.split_off(prefix), new: new.split_off(prefix) }
+    }
+
+    /// Inserts `count` neutral frames at `start` in every active controller.
+    pub fn insert(movie: &M64File, start: usize, count: usize) -> Result<Edit> {
+        let mut inputs: Controllers = [const { Vec::new() }; 4];
+        for i in M64File::active_controllers(movie.controller_flags)? {
+            inputs[i] = vec![Input::new(); count];
+        }
+        Ok(with_sample_count(movie, Edit::Insert { start, inputs }, movie.frames() + count))
+    }
+
+    /// Inserts `new` into `controller` at `start`, padding the other active controllers
+    /// with neutral frames so the controllers stay the same length.
+    pub fn insert_inputs(movie: &M64File, start: usize, controller: usize, new: Vec<Input>) -> Result<Edit> {
+        let active_controllers = M64File::active_controllers(movie.controller_flags)?;
+        if !active_controllers.contains(&controller) {
+            return Err(M64Error { message: format!("Controller {} is not present", controller + 1) }.into());
+        }
+        let mut inputs: Controllers = [const { Vec::new() }; 4];
+        for i in active_controllers {
+            inputs[i] = vec![Input::new(); new.len()];
+        }
+        let frames = movie.frames() + new.len();
+        inputs[controller] = new;
+        Ok(with_sample_count(movie, Edit::Insert { start, inputs }, frames))
+    }
+
+    /// Overwrites `controller` from `start` with `new`, appending the frames that run past the end.
+    pub fn overwrite(movie: &M64File, controller: usize, start: usize, mut new: Vec<Input>) -> Result<Edit> {
+        let len = movie.inputs[controller].len();
+        if start > len {
+            return Err(out_of_range(start, len));
+        }
+        let appended = new.split_off(new.len().min(len - start));
+        let replace = Edit::replace(movie, controller, start, new);
+        if appended.is_empty() {
+            return Ok(replace);
+        }
+        Ok(Edit::Batch(vec![replace, Self::insert_inputs(movie, len, controller, appended)?]))
+    }
+
+    /// Adds controller `from` of `other` in the unused port `to`, see `M64File::merge_controller`.
+    pub fn merge_port(movie: &M64File, other: &M64File, from: usize, to: usize) -> Result<Edit> {
+        // Merging into a copy of the header checks the ports and updates the flags
+        let mut merged = M64File::header_from_bytes(&movie.header_to_bytes())?;
+        merged.merge_controller(other, from, to)?;
+
+        let len = movie.inputs.iter().map(Vec::len).max().unwrap_or(0);
+        let merged_len = len.max(merged.inputs[to].len());
+        merged.num_samples = merged_len as u32;
+        let mut padding: Controllers = [const { Vec::new() }; 4];
+        for i in M64File::active_controllers(movie.controller_flags)? {
+            padding[i] = vec![Input::new(); merged_len - movie.inputs[i].len()];
+        }
+        let mut inputs: Controllers = [const { Vec::new() }; 4];
+        inputs[to] = std::mem::take(&mut merged.inputs[to]);
+        inputs[to].resize(merged_len, Input::new());
+        Ok(Edit::Batch(vec![
+            Edit::Header { old: movie.header_to_bytes(), new: merged.header_to_bytes() },
+            Edit::Insert { start: len, inputs: padding },
+            Edit::Insert { start: 0, inputs },
+        ]))
+    }
+
+    /// Replaces the header and every frame, e.g. with the result of a merge.
+    pub fn replace_movie(movie: &M64File, new: &M64File) -> Edit {
+        let len = movie.inputs.iter().map(Vec::len).max().unwrap_or(0);
+        Edit::Batch(vec![
+            Self::remove(movie, 0..len),
+            Self::header(movie, new),
+            Edit::Insert { start: 0, inputs: new.inputs.clone() },
+        ])
+    }
+
+    pub fn remove(movie: &M64File, range: Range<usize>) -> Edit {
+        let removed = movie.inputs.each_ref().map(|inputs| {
+            let end = range.end.min(inputs.len());
+            inputs.get(range.start..end).unwrap_or_default().to_vec()
+        });
+        let frames = movie.frames();
+        let removed_frames = range.end.min(frames).saturating_sub(range.start);
+        with_sample_count(movie, Edit::Remove { start: range.start, removed }, frames - removed_frames)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Edit::Header { old, new } => old == new,
+            Edit::SampleCount { old, new } => old == new,
+            Edit::Replace { old, new, .. } => old.is_empty() && new.is_empty(),
+            Edit::Insert { inputs, .. } => inputs.iter().all(Vec::is_empty),
+            Edit::Remove { removed, .. } => removed.iter().all(Vec::is_empty),
+            Edit::SwapPorts(a, b) => a == b,
+            Edit::Batch(edits) => edits.iter().all(Edit::is_empty),
+        }
+    }
+
+    pub fn apply(&self, movie: &mut M64File) -> Result<()> {
+        match self {
+            Edit::Header { new, .. } => {
+                movie.set_header(new)?;
+            }
+            Edit::SampleCount { new, .. } => {
+                movie.num_samples = *new;
+            }
+            Edit::Replace { controller, start, old, new } => {
+                let inputs = &mut movie.inputs[*controller];
+                if start + old.len() > inputs.len() {
+                    return Err(out_of_range(*start + old.len(), inputs.len()));
+                }
+                inputs.splice(*start..start + old.len(), new.iter().cloned());
+            }
+            Edit::Insert { start, inputs } => {
+                for (current, new) in movie.inputs.iter_mut().zip(inputs) {
+                    if !new.is_empty() && *start > current.len() {
+                        return Err(out_of_range(*start, current.len()));
+                    }
+                }
+                for (current, new) in movie.inputs.iter_mut().zip(inputs) {
+                    if !new.is_empty() {
+                        current.splice(*start..*start, new.iter().cloned());
+                    }
+                }
+            }
+            Edit::Remove { start, removed } => {
+                for (current, removed) in movie.inputs.iter_mut().zip(removed) {
+                    if !removed.is_empty() && start + removed.len() > current.len() {
+                        return Err(out_of_range(*start + removed.len(), current.len()));
+                    }
+                }
+                for (current, removed) in movie.inputs.iter_mut().zip(removed) {
+                    if !removed.is_empty() {
+                        current.drain(*start..start + removed.len());
+                    }
+                }
+            }
+            Edit::SwapPorts(a, b) => {
+                movie.swap_controllers(*a, *b)?;
+            }
+            Edit::Batch(edits) => {
+                for (i, edit) in edits.iter().enumerate() {
+                    if let Err(e) = edit.apply(movie) {
+                        // Roll back the part of the batch that was applied
+                        for edit in edits[..i].iter().rev() {
+                            edit.clone().invert().apply(movie)?;
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn invert(self) -> Edit {
+        match self {
+            Edit::Header { old, new } => Edit::Header { old: new, new: old },
+            Edit::SampleCount { old, new } => Edit::SampleCount { old: new, new: old },
+            Edit::Replace { controller, start, old, new } => Edit::Replace { controller, start, old: new, new: old },
+            Edit::Insert { start, inputs } => Edit::Remove { start, removed: inputs },
+            Edit::Remove { start, removed } => Edit::Insert { start, inputs: removed },
+            Edit::SwapPorts(a, b) => Edit::SwapPorts(a, b),
+            Edit::Batch(edits) => Edit::Batch(edits.into_iter().rev().map(Edit::invert).collect()),
+        }
+    }
+}
+
+/// Pairs `edit` with setting the header's sample count to `frames`, the length it leaves
+/// the movie, so that undoing it restores the old count too.
+fn with_sample_count(movie: &M64File, edit: Edit, frames: usize) -> Edit {
+    let count = Edit::SampleCount { old: movie.num_samples, new: frames as u32 };
+    if count.is_empty() {
+        return edit;
+    }
+    Edit::Batch(vec![edit, count])
+}
+
+fn out_of_range(index: usize, len: usize) -> anyhow::Error {
+    M64Error { message: format!("Edit touches frame {} but the movie only has {} frames", index, len) }.into()
+}
+
+/// Undo and redo stacks of the edits made to a movie.
+pub struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    limit: usize,
+}
+
+impl History {
+    pub fn new(limit: usize) -> History {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit,
+        }
+    }
+
+    /// Applies `edit` to `movie` and records it, discarding anything that could be redone.
+    pub fn apply(&mut self, movie: &mut M64File, edit: Edit) -> Result<()> {
+        if edit.is_empty() {
+            return Ok(());
+        }
+        edit.apply(movie)?;
+        self.redo.clear();
+        self.undo.push_back(edit);
+        if self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Reverts the last edit, returning false if there was nothing to undo.
+    pub fn undo(&mut self, movie: &mut M64File) -> Result<bool> {
+        let Some(edit) = self.undo.pop_back() else { return Ok(false) };
+        let inverse = edit.clone().invert();
+        if let Err(e) = inverse.apply(movie) {
+            self.undo.push_back(edit);
+            return Err(e);
+        }
+        self.redo.push(edit);
+        Ok(true)
+    }
+
+    pub fn redo(&mut self, movie: &mut M64File) -> Result<bool> {
+        let Some(edit) = self.redo.pop() else { return Ok(false) };
+        if let Err(e) = edit.apply(movie) {
+            self.redo.push(edit);
+            return Err(e);
+        }
+        self.undo.push_back(edit);
+        Ok(true)
+    }
+
+    /// The edit that undoing would apply to the movie, the inverse of the last edit.
+    pub fn next_undo(&self) -> Option<Edit> {
+        self.undo.back().map(|edit| edit.clone().invert())
+    }
+
+    pub fn next_redo(&self) -> Option<&Edit> {
+        self.redo.last()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::m64_handling::test_movie;
+
+    // Applies `edit`, checking that undoing restores the movie and redoing gives the edit again
+    fn check_undo(mut movie: M64File, edit: Edit) -> M64File {
+        let original = movie.to_bytes().unwrap();
+        let mut history = History::new(100);
+        history.apply(&mut movie, edit).unwrap();
+        let edited = movie.to_bytes().unwrap();
+        assert!(history.undo(&mut movie).unwrap());
+        assert_eq!(movie.to_bytes().unwrap(), original);
+        assert!(history.redo(&mut movie).unwrap());
+        assert_eq!(movie.to_bytes().unwrap(), edited);
+        movie
+    }
+
+    #[test]
+    fn replace_is_undone() {
+        let movie = test_movie(10, 0b0011);
+        let mut new = movie.inputs[0][2..5].to_vec();
+        new[1].b_button = true;
+        let edit = Edit::replace(&movie, 0, 2, new);
+        // Only the frame that differs is stored
+        assert!(matches!(&edit, Edit::Replace { start: 3, old, .. } if old.len() == 1));
+        assert!(check_undo(movie, edit).inputs[0][3].b_button);
+    }
+
+    #[test]
+    fn insert_is_undone() {
+        let movie = test_movie(10, 0b0011);
+        let edit = Edit::insert(&movie, 4, 3).unwrap();
+        assert_eq!(check_undo(movie, edit).frames(), 13);
+    }
+
+    #[test]
+    fn remove_is_undone() {
+        let movie = test_movie(10, 0b0011);
+        let edit = Edit::remove(&movie, 0..2);
+        assert_eq!(check_undo(movie, edit).frames(), 8);
+    }
+
+    #[test]
+    fn nothing_to_undo_or_redo() {
+        let mut movie = test_movie(10, 0b0001);
+        let mut history = History::new(100);
+        assert!(!history.undo(&mut movie).unwrap());
+        assert!(!history.redo(&mut movie).unwrap());
+        // Empty edits aren't recorded
+        let edit = Edit::replace(&movie, 0, 0, movie.inputs[0].clone());
+        history.apply(&mut movie, edit).unwrap();
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn insert_skips_unused_ports() {
+        let mut movie = test_movie(10, 0b0010);
+        let edit = Edit::insert(&movie, 5, 3).unwrap();
+        edit.apply(&mut movie).unwrap();
+        assert!(movie.inputs[0].is_empty());
+        assert_eq!(movie.inputs[1].len(), 13);
+        assert!(movie.to_bytes().is_ok());
+        assert!(Edit::insert_inputs(&movie, 0, 0, vec![Input::new()]).is_err());
+    }
+
+    #[test]
+    fn sample_count_follows_edits() {
+        let mut movie = test_movie(10, 0b0001);
+        let mut history = History::new(100);
+        let edit = Edit::insert(&movie, 10, 5).unwrap();
+        history.apply(&mut movie, edit).unwrap();
+        assert_eq!(movie.num_samples, 15);
+        let edit = Edit::remove(&movie, 0..3);
+        history.apply(&mut movie, edit).unwrap();
+        assert_eq!(movie.num_samples, 12);
+        history.undo(&mut movie).unwrap();
+        history.undo(&mut movie).unwrap();
+        assert_eq!(movie.num_samples, 10);
+        let edit = Edit::merge_port(&movie, &test_movie(20, 0b0001), 0, 2).unwrap();
+        history.apply(&mut movie, edit).unwrap();
+        assert_eq!(movie.num_samples, 20);
+        assert!(movie.validate().is_empty(), "{:?}", movie.validate());
+    }
+
+    #[test]
+    fn history_keeps_only_the_last_edits() {
+        let mut movie = test_movie(10, 0b0001);
+        let mut history = History::new(5);
+        for _ in 0..20 {
+            let edit = Edit::insert(&movie, 0, 1).unwrap();
+            history.apply(&mut movie, edit).unwrap();
+        }
+        assert_eq!(movie.num_samples, 30);
+        let mut undone = 0;
+        while history.undo(&mut movie).unwrap() {
+            undone += 1;
+        }
+        assert_eq!(undone, 5);
+        assert_eq!(movie.frames(), 25);
+        assert_eq!(movie.num_samples, 25);
+    }
+}
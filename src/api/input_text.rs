@@ -0,0 +1,99 @@
+use crate::api::m64_handling::{Button, Input, M64Error};
+use anyhow::Result;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+// Text form of a single frame: stick X and Y followed by the pressed buttons, e.g.
+// "  12  -34 A Z C^". Lines starting with '#' are comments.
+
+/// The order buttons are written in, most commonly used first.
+const BUTTON_ORDER: [Button; 14] = [
+    Button::A, Button::B, Button::Z, Button::Start,
+    Button::L, Button::R,
+    Button::CUp, Button::CDown, Button::CLeft, Button::CRight,
+    Button::DUp, Button::DDown, Button::DLeft, Button::DRight,
+];
+
+impl Display for Input {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:4} {:4}", self.x, self.y)?;
+        for button in BUTTON_ORDER.into_iter().filter(|&button| self.pressed(button)) {
+            write!(f, " {}", button.label())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Input {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Input> {
+        let mut tokens = s.split_whitespace();
+        let mut input = Input::new();
+        input.x = parse_axis(tokens.next(), "X")?;
+        input.y = parse_axis(tokens.next(), "Y")?;
+        for token in tokens {
+            input.set(token.parse()?, true);
+        }
+        Ok(input)
+    }
+}
+
+fn parse_axis(token: Option<&str>, axis: &str) -> Result<i8> {
+    let token = token.ok_or(M64Error { message: format!("Missing stick {} value", axis) })?;
+    token.parse().map_err(|_| M64Error { message: format!("Invalid stick {} value \"{}\"", axis, token) }.into())
+}
+
+/// Writes one line per frame.
+pub fn encode(inputs: &[Input]) -> String {
+    let mut text = String::from("#    X    Y Buttons\n");
+    for input in inputs {
+        text.push_str(&input.to_string());
+        text.push('\n');
+    }
+    text
+}
+
+/// Reads frames written by [`encode`], skipping blank lines and comments.
+pub fn decode(text: &str) -> Result<Vec<Input>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| line.parse().map_err(|e| M64Error { message: format!("Line {}: {}", i + 1, e) }.into()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip() {
+        let mut every_button = Input { x: -128, y: 127, ..Input::new() };
+        Button::ALL.into_iter().for_each(|button| every_button.set(button, true));
+        let inputs = vec![Input::new(), every_button, Input { x: 12, y: -34, a_button: true, ..Input::new() }];
+        let text = encode(&inputs);
+        assert!(text.contains("  12  -34 A\n"));
+        assert_eq!(decode(&text).unwrap(), inputs);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let inputs = decode("# header\n\n  1 2 start c^\n   # note\n0 0\n").unwrap();
+        assert_eq!(inputs.len(), 2);
+        assert!(inputs[0].pressed(Button::Start) && inputs[0].pressed(Button::CUp));
+        assert_eq!((inputs[0].x, inputs[0].y), (1, 2));
+    }
+
+    #[test]
+    fn invalid_lines_name_their_line() {
+        for (text, error) in [
+            ("0 0\n5\n", "Line 2: Missing stick Y value"),
+            ("128 0", "Line 1: Invalid stick X value \"128\""),
+            ("0 x", "Line 1: Invalid stick Y value \"x\""),
+            ("0 0 A Q", "Line 1: Unknown button \"Q\""),
+        ] {
+            assert_eq!(decode(text).unwrap_err().to_string(), error);
+        }
+    }
+}
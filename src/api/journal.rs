@@ -0,0 +1,217 @@
+use crate::api::file_handling::save_file;
+use crate::api::history::Edit;
+use crate::api::m64_handling::M64File;
+use crate::api::movie_text;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// The journal is a file next to the movie with one JSON entry per line: a snapshot of the whole
+// movie in the text form, if one has been taken, then every edit made since. Undoing is recorded
+// as the inverse edit. Each entry is synced to disk as it is written, so after a crash the
+// journal holds everything up to the last edit, and at worst a partly written last line.
+
+/// How often the journal is replaced by a snapshot of the movie, if it was edited since the
+/// last one, which keeps it short.
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize)]
+enum Entry {
+    Snapshot(String),
+    Edit(Edit),
+}
+
+/// Where the journal of the movie at `path` is kept, e.g. `movie.m64.journal`.
+pub fn journal_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".journal");
+    path.with_file_name(name)
+}
+
+/// Unsaved changes to a movie, recorded as they are made.
+///
+/// The journal file is only created by the first edit, and removed by [`Journal::clear`] once
+/// the changes are saved. [`Journal::autosave`] is meant to be called every [`AUTOSAVE_INTERVAL`].
+pub struct Journal {
+    path: PathBuf,
+    file: Option<File>,
+    // Whether edits were recorded since the last snapshot
+    edited: bool,
+}
+
+impl Journal {
+    pub fn new(movie_path: &Path) -> Journal {
+        Journal { path: journal_path(movie_path), file: None, edited: false }
+    }
+
+    /// Records `edit`, which has just been applied to the movie.
+    pub fn record(&mut self, edit: &Edit) -> Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(OpenOptions::new().create(true).append(true).open(&self.path)?),
+        };
+        writeln!(file, "{}", serde_json::to_string(&Entry::Edit(edit.clone()))?)?;
+        file.sync_data()?;
+        self.edited = true;
+        Ok(())
+    }
+
+    /// Takes a snapshot of `movie` if it was edited since the last one.
+    pub fn autosave(&mut self, movie: &M64File) -> Result<()> {
+        match self.edited {
+            true => self.snapshot(movie),
+            false => Ok(()),
+        }
+    }
+
+    /// Replaces the journal with a snapshot of `movie`.
+    pub fn snapshot(&mut self, movie: &M64File) -> Result<()> {
+        let line = serde_json::to_string(&Entry::Snapshot(movie_text::encode(movie)))? + "\n";
+        save_file(&self.path, &line.into_bytes())?;
+        self.file = Some(OpenOptions::new().append(true).open(&self.path)?);
+        self.edited = false;
+        Ok(())
+    }
+
+    /// Removes the journal, once the movie is saved or its changes are thrown away.
+    pub fn clear(&mut self) -> Result<()> {
+        self.file = None;
+        self.edited = false;
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The movie as it was before a crash, rebuilt from its journal.
+pub struct Recovery {
+    pub movie: M64File,
+    pub edits: usize,
+    pub from_snapshot: bool,
+    // Entries at the end of the journal that couldn't be read or applied
+    pub skipped: usize,
+}
+
+/// Replays the journal of the movie at `movie_path`, if there is one, on `saved`, the movie
+/// as it is on disk. Replaying stops at the first entry that can't be read or applied.
+pub fn recover(movie_path: &Path, saved: &M64File) -> Result<Option<Recovery>> {
+    let text = match fs::read_to_string(journal_path(movie_path)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+    if lines.is_empty() {
+        return Ok(None);
+    }
+    let mut recovery = Recovery { movie: saved.clone(), edits: 0, from_snapshot: false, skipped: 0 };
+    for (i, line) in lines.iter().enumerate() {
+        let replayed = match serde_json::from_str::<Entry>(line) {
+            Ok(Entry::Snapshot(text)) => movie_text::decode(&text).map(|movie| {
+                recovery.movie = movie;
+                recovery.from_snapshot = true;
+            }),
+            Ok(Entry::Edit(edit)) => edit.apply(&mut recovery.movie).map(|()| recovery.edits += 1),
+            Err(e) => Err(e.into()),
+        };
+        if replayed.is_err() {
+            recovery.skipped = lines.len() - i;
+            break;
+        }
+    }
+    Ok(Some(recovery))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::file_handling::TempDir;
+    use crate::api::history::History;
+    use crate::api::m64_handling::test_movie;
+
+    // Applies and records each edit made by `edits` in turn
+    fn edit(movie: &mut M64File, journal: &mut Journal, edits: &[fn(&M64File) -> Edit]) {
+        let mut history = History::new(100);
+        for make_edit in edits {
+            let edit = make_edit(movie);
+            history.apply(movie, edit.clone()).unwrap();
+            journal.record(&edit).unwrap();
+        }
+    }
+
+    #[test]
+    fn autosave_skips_unedited_movies() {
+        let directory = TempDir::new("journal");
+        let path = directory.join("movie.m64");
+        let saved = test_movie(20, 0b0001);
+        Journal::new(&path).autosave(&saved).unwrap();
+        assert!(!journal_path(&path).exists());
+        assert!(recover(&path, &saved).unwrap().is_none());
+    }
+
+    #[test]
+    fn recovers_edits_without_a_snapshot() {
+        let directory = TempDir::new("journal");
+        let path = directory.join("movie.m64");
+        let saved = test_movie(20, 0b0001);
+        let mut movie = saved.clone();
+        let mut journal = Journal::new(&path);
+        edit(&mut movie, &mut journal, &[|movie| Edit::remove(movie, 2..5), |movie| Edit::insert(movie, 0, 2).unwrap()]);
+
+        let recovery = recover(&path, &saved).unwrap().unwrap();
+        assert!(!recovery.from_snapshot);
+        assert_eq!((recovery.edits, recovery.skipped), (2, 0));
+        assert_eq!(recovery.movie.to_bytes().unwrap(), movie.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn recovers_edits_after_autosave() {
+        let directory = TempDir::new("journal");
+        let path = directory.join("movie.m64");
+        let saved = test_movie(20, 0b0001);
+        let mut movie = saved.clone();
+        let mut journal = Journal::new(&path);
+        edit(&mut movie, &mut journal, &[|movie| Edit::remove(movie, 2..5)]);
+        journal.autosave(&movie).unwrap();
+        edit(&mut movie, &mut journal, &[|movie| Edit::insert(movie, 0, 2).unwrap()]);
+
+        let recovery = recover(&path, &saved).unwrap().unwrap();
+        assert!(recovery.from_snapshot);
+        assert_eq!((recovery.edits, recovery.skipped), (1, 0));
+        assert_eq!(recovery.movie.to_bytes().unwrap(), movie.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn partly_written_entries_are_skipped() {
+        let directory = TempDir::new("journal");
+        let path = directory.join("movie.m64");
+        let saved = test_movie(20, 0b0001);
+        let mut movie = saved.clone();
+        let mut journal = Journal::new(&path);
+        edit(&mut movie, &mut journal, &[|movie| Edit::remove(movie, 2..5)]);
+        let mut file = OpenOptions::new().append(true).open(journal_path(&path)).unwrap();
+        write!(file, "{{\"Edit\":{{\"Rem").unwrap();
+
+        let recovery = recover(&path, &saved).unwrap().unwrap();
+        assert_eq!((recovery.edits, recovery.skipped), (1, 1));
+        assert_eq!(recovery.movie.to_bytes().unwrap(), movie.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn clearing_removes_the_journal() {
+        let directory = TempDir::new("journal");
+        let path = directory.join("movie.m64");
+        let saved = test_movie(20, 0b0001);
+        let mut movie = saved.clone();
+        let mut journal = Journal::new(&path);
+        edit(&mut movie, &mut journal, &[|movie| Edit::remove(movie, 2..5)]);
+        journal.clear().unwrap();
+        assert!(recover(&path, &saved).unwrap().is_none());
+        // Clearing again, with no journal left, is fine
+        journal.clear().unwrap();
+    }
+}
@@ -1,14 +1,19 @@
 use std::ascii::Char as AsciiChar;
 use std::fmt::{Display, Formatter};
 use std::ops::{Range, Shr};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 use bitvec::prelude::BitArray;
 use bitvec::view::BitViewSized;
 use anyhow::{Result};
+use serde::{Deserialize, Serialize};
+use crate::api::timecode::Timing;
 
 pub type Controllers = [Vec<Input>; 4];
 pub type ByteVec = Vec<u8>;
 
 
+#[derive(Clone)]
 pub struct M64File {
     pub signature: [u8; 4],                 //0x00 4 bytes
     pub version: u32,                       //0x04
@@ -34,7 +39,7 @@ pub struct M64File {
 
 #[derive(Debug)]
 pub struct M64Error {
-    message: String,
+    pub(crate) message: String,
 }
 
 
@@ -58,7 +63,7 @@ impl std::error::Error for M64Error {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Input {
     pub r_dpad: bool,
     pub l_dpad: bool,
@@ -78,8 +83,66 @@ pub struct Input {
     pub y: i8,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Button {
+    DRight,
+    DLeft,
+    DDown,
+    DUp,
+    Start,
+    Z,
+    B,
+    A,
+    CRight,
+    CLeft,
+    CDown,
+    CUp,
+    R,
+    L,
+}
+
+impl Button {
+    // In bit order of the sample, e.g. DRight is bit 0
+    pub const ALL: [Button; 14] = [
+        Button::DRight, Button::DLeft, Button::DDown, Button::DUp,
+        Button::Start, Button::Z, Button::B, Button::A,
+        Button::CRight, Button::CLeft, Button::CDown, Button::CUp,
+        Button::R, Button::L,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Button::DRight => "D>",
+            Button::DLeft => "D<",
+            Button::DDown => "Dv",
+            Button::DUp => "D^",
+            Button::Start => "S",
+            Button::Z => "Z",
+            Button::B => "B",
+            Button::A => "A",
+            Button::CRight => "C>",
+            Button::CLeft => "C<",
+            Button::CDown => "Cv",
+            Button::CUp => "C^",
+            Button::R => "R",
+            Button::L => "L",
+        }
+    }
+}
+
+impl FromStr for Button {
+    type Err = M64Error;
+
+    fn from_str(s: &str) -> Result<Button, M64Error> {
+        Button::ALL.into_iter()
+            .find(|button| button.label().eq_ignore_ascii_case(s))
+            .or_else(|| s.eq_ignore_ascii_case("start").then_some(Button::Start))
+            .ok_or(M64Error { message: format!("Unknown button \"{}\"", s) })
+    }
+}
+
 impl Input {
-    fn new() -> Input {
+    pub fn new() -> Input {
         Input {
             r_dpad: false,
             l_dpad: false,
@@ -99,6 +162,43 @@ impl Input {
             y: 0,
         }
     }
+    pub fn pressed(&self, button: Button) -> bool {
+        match button {
+            Button::DRight => self.r_dpad,
+            Button::DLeft => self.l_dpad,
+            Button::DDown => self.d_dpad,
+            Button::DUp => self.u_dpad,
+            Button::Start => self.start,
+            Button::Z => self.z_trig,
+            Button::B => self.b_button,
+            Button::A => self.a_button,
+            Button::CRight => self.c_right,
+            Button::CLeft => self.c_left,
+            Button::CDown => self.c_down,
+            Button::CUp => self.c_up,
+            Button::R => self.r_trig,
+            Button::L => self.l_trig,
+        }
+    }
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        let field = match button {
+            Button::DRight => &mut self.r_dpad,
+            Button::DLeft => &mut self.l_dpad,
+            Button::DDown => &mut self.d_dpad,
+            Button::DUp => &mut self.u_dpad,
+            Button::Start => &mut self.start,
+            Button::Z => &mut self.z_trig,
+            Button::B => &mut self.b_button,
+            Button::A => &mut self.a_button,
+            Button::CRight => &mut self.c_right,
+            Button::CLeft => &mut self.c_left,
+            Button::CDown => &mut self.c_down,
+            Button::CUp => &mut self.c_up,
+            Button::R => &mut self.r_trig,
+            Button::L => &mut self.l_trig,
+        };
+        *field = pressed;
+    }
     fn parse(input_bytes: &ByteVec, controller_flags: u8) -> Result<Controllers> {
         let mut inputs: Controllers = [const { Vec::new() }; 4];
         let mut active_controllers = M64File::active_controllers(controller_flags as u32)?;
@@ -159,8 +259,25 @@ impl Input {
     }
 }
 
+pub fn ascii_to_string(chars: &[AsciiChar]) -> String {
+    chars.iter().take_while(|c| c.to_u8() != 0).map(|c| c.to_char()).collect()
+}
+
+/// Pads `text` with NULs to fill a fixed-size header field.
+pub fn string_to_ascii<const N: usize>(text: &str) -> Result<[AsciiChar; N], M64Error> {
+    let Some(chars) = text.as_ascii() else {
+        return Err(M64Error { message: format!("\"{}\" is not ASCII", text) });
+    };
+    if chars.len() > N {
+        return Err(M64Error { message: format!("\"{}\" is longer than {} characters", text, N) });
+    }
+    let mut field = [0_u8.as_ascii().unwrap(); N];
+    field[..chars.len()].copy_from_slice(chars);
+    Ok(field)
+}
+
 impl M64File {
-    fn new() -> M64File {
+    pub fn new() -> M64File {
         M64File {
             signature: [0x4D, 0x36, 0x34, 0x1A],
             version: 0x03,
@@ -185,6 +302,18 @@ impl M64File {
         }
     }
     pub fn from_bytes(buf: &ByteVec) -> Result<M64File> {
+        let mut m64 = Self::header_from_bytes(buf)?;
+        // A movie cut short by a crash can end partway through a sample, which is dropped
+        let end = buf.len() - Self::partial_sample_bytes(buf);
+        m64.inputs = Input::parse(&buf[0x400..end].to_vec(), buf[0x20])?;
+        Ok(m64)
+    }
+    pub fn partial_sample_bytes(buf: &[u8]) -> usize {
+        // Bytes after the last whole 4 byte sample
+        buf.len().saturating_sub(0x400) % 4
+    }
+    pub fn header_from_bytes(buf: &[u8]) -> Result<M64File> {
+        // Parses the 0x400 byte header only, leaving the inputs empty
         if buf.len() < 0x400 {
             return Err(M64Error { message: "File is too small".to_string() }.into());
         }
@@ -208,7 +337,7 @@ impl M64File {
             rsp_plugin: *<&[u8] as TryInto<[u8; 64]>>::try_into(&buf[0x1E2..0x222])?.as_ascii().unwrap(),
             author: *<&[u8] as TryInto<[u8; 222]>>::try_into(&buf[0x222..0x300])?.as_ascii().unwrap(),
             movie_desc: *<&[u8] as TryInto<[u8; 256]>>::try_into(&buf[0x300..0x400])?.as_ascii().unwrap(),
-            inputs: Input::parse(&buf[0x400..].to_vec(), buf[0x20])?,
+            inputs: [const { Vec::new() }; 4],
         };
 
         Ok(m64)
@@ -225,10 +354,90 @@ impl M64File {
             ok_or(M64Error { message: "No active controllers".to_string() }))
 
     }
+    pub fn validate(&self) -> Vec<String> {
+        // Describes each way the movie breaks the format that players rely on
+        let mut problems = Vec::new();
+        if self.signature != [0x4D, 0x36, 0x34, 0x1A] {
+            problems.push(format!("Signature is {:02X?} instead of \"M64\\x1A\"", self.signature));
+        }
+        if self.version != 3 {
+            problems.push(format!("Version is {} instead of 3", self.version));
+        }
+        if ![1, 2, 4].contains(&self.movie_start_type) {
+            problems.push(format!("Unknown movie start type {}", self.movie_start_type));
+        }
+        let Ok(active_controllers) = Self::active_controllers(self.controller_flags) else {
+            problems.push("No controllers are connected".to_string());
+            return problems;
+        };
+        if self.controller_count as usize != active_controllers.len() {
+            problems.push(format!(
+                "Controller count is {} but {} controllers are connected",
+                self.controller_count,
+                active_controllers.len()
+            ));
+        }
+        let first = active_controllers[0];
+        let frames = self.inputs[first].len();
+        for &i in &active_controllers[1..] {
+            if self.inputs[i].len() != frames {
+                problems.push(format!(
+                    "Controller {} has {} frames but controller {} has {}",
+                    i + 1, self.inputs[i].len(), first + 1, frames
+                ));
+            }
+        }
+        for i in (0..4).filter(|i| !active_controllers.contains(i) && !self.inputs[*i].is_empty()) {
+            problems.push(format!("Controller {} isn't connected but has inputs", i + 1));
+        }
+        if self.num_samples as usize != frames {
+            problems.push(format!("Sample count is {} but the movie has {} frames", self.num_samples, frames));
+        }
+        if (self.vi_count as usize) < frames {
+            problems.push(format!("VI count {} is less than the {} frames", self.vi_count, frames));
+        }
+        problems
+    }
+    pub fn fix_header(&mut self) -> Vec<String> {
+        // Brings the header fields in line with the inputs, describing each change. Problems
+        // with the inputs themselves are left for validate to report
+        let mut changes = Vec::new();
+        if self.signature != [0x4D, 0x36, 0x34, 0x1A] {
+            self.signature = [0x4D, 0x36, 0x34, 0x1A];
+            changes.push("Set the signature to \"M64\\x1A\"".to_string());
+        }
+        if self.version != 3 {
+            changes.push(format!("Set the version from {} to 3", self.version));
+            self.version = 3;
+        }
+        let Ok(active_controllers) = Self::active_controllers(self.controller_flags) else {
+            return changes;
+        };
+        if self.controller_count as usize != active_controllers.len() {
+            changes.push(format!("Set the controller count from {} to {}", self.controller_count, active_controllers.len()));
+            self.controller_count = active_controllers.len() as u8;
+        }
+        let frames = self.frames();
+        if self.num_samples as usize != frames {
+            changes.push(format!("Set the sample count from {} to {}", self.num_samples, frames));
+            self.num_samples = frames as u32;
+        }
+        // Every frame takes at least one VI
+        if (self.vi_count as usize) < frames {
+            changes.push(format!("Set the VI count from {} to {}", self.vi_count, frames));
+            self.vi_count = frames as u32;
+        }
+        changes
+    }
     pub fn to_bytes(&self) -> Result<ByteVec> {
         let active_controllers = Self::active_controllers(self.controller_flags)?;
-        let mut sample_bytes: ByteVec = Input::samples_to_bytes(&self.inputs, &active_controllers)?;
-        let mut buffer: ByteVec = vec![0; 0x400 + sample_bytes.len()];
+        let sample_bytes: ByteVec = Input::samples_to_bytes(&self.inputs, &active_controllers)?;
+        let mut buffer = self.header_to_bytes();
+        buffer.extend_from_slice(&sample_bytes);
+        Ok(buffer)
+    }
+    pub fn header_to_bytes(&self) -> ByteVec {
+        let mut buffer: ByteVec = vec![0; 0x400];
         buffer[0x0..0x4].copy_from_slice(&self.signature);
         buffer[0x4..0x8].copy_from_slice(&self.version.to_le_bytes());
         buffer[0x8..0xC].copy_from_slice(&self.uid.to_le_bytes());
@@ -248,8 +457,37 @@ impl M64File {
         buffer[0x1E2..0x222].copy_from_slice(&self.rsp_plugin.as_bytes());
         buffer[0x222..0x300].copy_from_slice(&self.author.as_bytes());
         buffer[0x300..0x400].copy_from_slice(&self.movie_desc.as_bytes());
-        buffer[0x400..].copy_from_slice(&sample_bytes);
-        Ok(buffer)
+        buffer
+    }
+    pub fn header_fields(&self) -> Vec<(&'static str, String)> {
+        // Header fields by name, with the text fields cut at the first NUL
+        vec![
+            ("signature", format!("{:02X?}", self.signature)),
+            ("version", self.version.to_string()),
+            ("uid", self.uid.to_string()),
+            ("vi_count", self.vi_count.to_string()),
+            ("rerecord_count", self.rerecord_count.to_string()),
+            ("vi_per_second", self.vi_per_second.to_string()),
+            ("controller_count", self.controller_count.to_string()),
+            ("num_samples", self.num_samples.to_string()),
+            ("movie_start_type", self.movie_start_type.to_string()),
+            ("controller_flags", format!("{:#06X}", self.controller_flags)),
+            ("internal_name", ascii_to_string(&self.internal_name)),
+            ("crc32", format!("{:08X}", self.crc32)),
+            ("country_code", format!("{:#06X}", self.country_code)),
+            ("video_plugin", ascii_to_string(&self.video_plugin)),
+            ("sound_plugin", ascii_to_string(&self.sound_plugin)),
+            ("input_plugin", ascii_to_string(&self.input_plugin)),
+            ("rsp_plugin", ascii_to_string(&self.rsp_plugin)),
+            ("author", ascii_to_string(&self.author)),
+            ("movie_desc", ascii_to_string(&self.movie_desc)),
+        ]
+    }
+    pub fn set_header(&mut self, header: &[u8]) -> Result<&mut M64File> {
+        let mut m64 = Self::header_from_bytes(header)?;
+        m64.inputs = std::mem::take(&mut self.inputs);
+        *self = m64;
+        Ok(self)
     }
     pub fn remove_inputs(&mut self, range: &Range<usize>) -> Result<&mut M64File> {
         let active_controllers = Self::active_controllers(self.controller_flags)?;
@@ -268,4 +506,227 @@ impl M64File {
         }
         Ok(self)
     }
+    fn check_port(port: usize) -> Result<(), M64Error> {
+        (port < 4).then_some(()).ok_or(M64Error { message: format!("Invalid controller port {}", port + 1) })
+    }
+    pub fn swap_controllers(&mut self, a: usize, b: usize) -> Result<&mut M64File> {
+        // Swaps the inputs of two ports along with their present, mempak and rumblepak flags,
+        // moving a controller if one of the ports is unused
+        Self::check_port(a)?;
+        Self::check_port(b)?;
+        self.inputs.swap(a, b);
+        for offset in [0, 4, 8] {
+            let (bit_a, bit_b) = ((self.controller_flags >> (a + offset)) & 1, (self.controller_flags >> (b + offset)) & 1);
+            self.controller_flags &= !((1 << (a + offset)) | (1 << (b + offset)));
+            self.controller_flags |= (bit_a << (b + offset)) | (bit_b << (a + offset));
+        }
+        Ok(self)
+    }
+    pub fn move_controller(&mut self, from: usize, to: usize) -> Result<&mut M64File> {
+        let active_controllers = Self::active_controllers(self.controller_flags)?;
+        if !active_controllers.contains(&from) {
+            return Err(M64Error { message: format!("Controller {} is not present", from + 1) }.into());
+        }
+        if active_controllers.contains(&to) {
+            return Err(M64Error { message: format!("Controller {} is already in use", to + 1) }.into());
+        }
+        self.swap_controllers(from, to)
+    }
+    pub fn merge_controller(&mut self, other: &M64File, from: usize, to: usize) -> Result<&mut M64File> {
+        // Copies controller `from` of `other` into the unused port `to`, along with its mempak
+        // and rumblepak flags, padding with neutral inputs so every controller has the same
+        // number of frames
+        Self::check_port(to)?;
+        let active_controllers = Self::active_controllers(self.controller_flags)?;
+        if active_controllers.contains(&to) {
+            return Err(M64Error { message: format!("Controller {} is already in use", to + 1) }.into());
+        }
+        if !Self::active_controllers(other.controller_flags)?.contains(&from) {
+            return Err(M64Error { message: format!("Controller {} is not present in the other movie", from + 1) }.into());
+        }
+        self.inputs[to] = other.inputs[from].clone();
+        // Present, mempak and rumblepak flags of `from`, moved to `to`
+        let flags = other.controller_flags >> from & 0x111;
+        self.controller_flags = self.controller_flags & !(0x111 << to) | flags << to;
+        self.controller_count += 1;
+        let frames = self.inputs.iter().map(Vec::len).max().unwrap_or(0);
+        for i in Self::active_controllers(self.controller_flags)? {
+            self.inputs[i].resize(frames, Input::new());
+        }
+        Ok(self)
+    }
+    pub fn frames(&self) -> usize {
+        // Length of the longest connected controller
+        Self::active_controllers(self.controller_flags)
+            .map_or(0, |active| active.iter().map(|&i| self.inputs[i].len()).max().unwrap_or(0))
+    }
+    pub fn concat(segments: &[M64File]) -> Result<M64File> {
+        // Joins the segments one after the other, keeping the header of the first. A controller
+        // connected in any segment is connected in the result, with neutral inputs where a
+        // segment doesn't have it, and its paks come from the first segment that has it.
+        // The rerecord and VI counts are the sums of the segments'
+        let Some(first) = segments.first() else {
+            return Err(M64Error { message: "No movies to join".to_string() }.into());
+        };
+        let mut movie = first.clone();
+        movie.controller_flags = 0;
+        for port in 0..4 {
+            if let Some(segment) = segments.iter().find(|segment| segment.controller_flags >> port & 1 == 1) {
+                movie.controller_flags |= segment.controller_flags & (0x111 << port);
+            }
+        }
+        let active_controllers = Self::active_controllers(movie.controller_flags)?;
+        movie.inputs = Default::default();
+        movie.vi_count = 0;
+        movie.rerecord_count = 0;
+        for segment in segments {
+            let frames = segment.frames();
+            for &i in &active_controllers {
+                let start = movie.inputs[i].len();
+                let present = segment.controller_flags >> i & 1 == 1;
+                movie.inputs[i].extend_from_slice(if present { &segment.inputs[i] } else { &[] });
+                movie.inputs[i].resize(start + frames, Input::new());
+            }
+            movie.vi_count = movie.vi_count.saturating_add(segment.vi_count);
+            movie.rerecord_count = movie.rerecord_count.saturating_add(segment.rerecord_count);
+        }
+        movie.controller_count = active_controllers.len() as u8;
+        movie.num_samples = movie.frames() as u32;
+        Ok(movie)
+    }
+    pub fn split_at(&self, frame: usize) -> Result<(M64File, M64File)> {
+        // Cuts the movie before `frame`. The VI count is shared out by the average VIs per
+        // frame, and the rerecords stay with the first part, so joining the parts again gives
+        // back the same header. The second part can't play from power-on, so it starts from
+        // a snapshot, which has to be saved at the cut, and gets a uid of its own to match it
+        let frames = self.frames();
+        if frame > frames {
+            return Err(M64Error { message: format!("Frame {} is past the end of the movie's {} frames", frame, frames) }.into());
+        }
+        let mut first = self.clone();
+        let mut second = self.clone();
+        for i in Self::active_controllers(self.controller_flags)? {
+            let cut = frame.min(self.inputs[i].len());
+            first.inputs[i].truncate(cut);
+            second.inputs[i].drain(..cut);
+        }
+        first.num_samples = frame as u32;
+        second.num_samples = (frames - frame) as u32;
+        first.vi_count = (Timing::of(self).frame_to_vi(frame) as u32).min(self.vi_count);
+        second.vi_count = self.vi_count - first.vi_count;
+        second.rerecord_count = 0;
+        if frame > 0 {
+            second.movie_start_type = 1;
+            second.uid = new_uid(self.uid);
+        }
+        Ok((first, second))
+    }
+}
+
+fn new_uid(old: i32) -> i32 {
+    // Mupen uses the time of recording as the uid
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() as i32);
+    if now == old { now.wrapping_add(1) } else { now }
+}
+
+#[cfg(test)]
+pub(crate) fn test_movie(frames: usize, controller_flags: u32) -> M64File {
+    // A valid movie whose frames all differ, for the tests of the other modules
+    let mut movie = M64File::new();
+    movie.controller_flags = controller_flags;
+    movie.controller_count = (controller_flags & 0xF).count_ones() as u8;
+    movie.num_samples = frames as u32;
+    movie.vi_count = 2 * frames as u32;
+    movie.vi_per_second = 60;
+    movie.movie_start_type = 2;
+    for i in M64File::active_controllers(controller_flags).unwrap() {
+        movie.inputs[i] = (0..frames)
+            .map(|frame| Input { x: (frame % 100) as i8, y: i as i8, a_button: frame % 3 == 0, ..Input::new() })
+            .collect();
+    }
+    movie
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip() {
+        let movie = test_movie(50, 0b0101 | 0x10);
+        let bytes = movie.to_bytes().unwrap();
+        assert_eq!(M64File::from_bytes(&bytes).unwrap().to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn fix_header_matches_inputs() {
+        let mut movie = test_movie(50, 0b0011);
+        movie.num_samples = 3;
+        movie.vi_count = 1;
+        movie.controller_count = 4;
+        assert_eq!(movie.fix_header().len(), 3);
+        assert!(movie.validate().is_empty(), "{:?}", movie.validate());
+    }
+
+    #[test]
+    fn split_parts_have_their_own_header() {
+        let movie = test_movie(100, 0b0001);
+        let (first, second) = movie.split_at(40).unwrap();
+        assert_eq!((first.num_samples, second.num_samples), (40, 60));
+        assert_eq!((first.vi_count, second.vi_count), (80, 120));
+        assert_eq!((first.movie_start_type, second.movie_start_type), (2, 1));
+        assert_eq!(first.uid, movie.uid);
+        assert_ne!(second.uid, movie.uid);
+        assert!(first.validate().is_empty() && second.validate().is_empty());
+        assert_eq!(second.inputs[0][0], movie.inputs[0][40]);
+    }
+
+    #[test]
+    fn split_parts_concat_back() {
+        let movie = test_movie(100, 0b0001);
+        let (first, second) = movie.split_at(40).unwrap();
+        let joined = M64File::concat(&[first, second]).unwrap();
+        assert_eq!(joined.to_bytes().unwrap(), movie.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn split_is_limited_to_the_movie() {
+        let movie = test_movie(100, 0b0001);
+        let (first, second) = movie.split_at(100).unwrap();
+        assert_eq!((first.frames(), second.frames()), (100, 0));
+        assert!(movie.split_at(101).is_err());
+    }
+
+    #[test]
+    fn concat_connects_every_controller() {
+        let joined = M64File::concat(&[test_movie(10, 0b0001), test_movie(5, 0b0010 | 0x20)]).unwrap();
+        assert_eq!(joined.controller_flags, 0b0011 | 0x20);
+        assert_eq!(joined.controller_count, 2);
+        assert_eq!((joined.inputs[0].len(), joined.inputs[1].len()), (15, 15));
+        assert_eq!(joined.inputs[1][0], Input::new());
+        assert_eq!((joined.vi_count, joined.num_samples), (30, 15));
+        assert!(joined.validate().is_empty(), "{:?}", joined.validate());
+    }
+
+    #[test]
+    fn merged_controller_keeps_its_paks() {
+        let mut movie = test_movie(10, 0b0001 | 0x10);
+        let other = test_movie(20, 0b0110 | 0x400);
+        movie.merge_controller(&other, 2, 3).unwrap();
+        assert_eq!(movie.controller_flags, 0b1001 | 0x10 | 0x800);
+        assert_eq!(movie.controller_count, 2);
+        assert_eq!(movie.inputs[3], other.inputs[2]);
+        assert_eq!(movie.inputs[0].len(), 20);
+        assert!(movie.merge_controller(&other, 1, 0).is_err());
+        assert!(movie.merge_controller(&other, 0, 1).is_err());
+    }
+
+    #[test]
+    fn truncated_sample_is_dropped() {
+        let mut bytes = test_movie(10, 0b0001).to_bytes().unwrap();
+        bytes.truncate(bytes.len() - 1);
+        let movie = M64File::from_bytes(&bytes).unwrap();
+        assert_eq!(movie.inputs[0].len(), 9);
+        assert_eq!(M64File::partial_sample_bytes(&bytes), 3);
+    }
 }
@@ -0,0 +1,325 @@
+use crate::api::diff::diff_inputs;
+use crate::api::m64_handling::{Input, M64File};
+use crate::api::movie_text;
+use crate::api::timecode::Timing;
+use anyhow::Result;
+use std::fmt::Write;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+/// Frames of one controller that both sides changed differently.
+#[derive(Clone, Debug)]
+pub struct Conflict {
+    pub controller: usize,
+    pub base: Range<usize>,
+    pub ours: Vec<Input>,
+    pub theirs: Vec<Input>,
+    pub resolution: Option<Side>,
+}
+
+impl Conflict {
+    pub fn resolved(&self) -> &[Input] {
+        match self.resolution {
+            Some(Side::Theirs) => &self.theirs,
+            _ => &self.ours,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Chunk {
+    Clean(Vec<Input>),
+    // Index into the conflicts of the merge
+    Conflict(usize),
+}
+
+/// Result of a three-way merge, with the conflicts left to resolve.
+///
+/// Unresolved conflicts and header fields changed on both sides default to our version.
+#[derive(Clone)]
+pub struct Merge {
+    header: M64File,
+    pub header_conflicts: Vec<&'static str>,
+    chunks: [Vec<Chunk>; 4],
+    pub conflicts: Vec<Conflict>,
+}
+
+// A change one side made to the base, from diff_inputs
+struct Change {
+    side: Side,
+    base: Range<usize>,
+    inputs: Vec<Input>,
+}
+
+// Header fields that follow from the merged inputs, see Merge::to_movie
+const DERIVED_FIELDS: [&str; 2] = ["num_samples", "vi_count"];
+
+pub fn merge(base: &M64File, ours: &M64File, theirs: &M64File) -> Result<Merge> {
+    let mut header = M64File::header_from_bytes(&base.header_to_bytes())?;
+    let mut header_conflicts = Vec::new();
+    let fields = base.header_fields().into_iter().zip(ours.header_fields()).zip(theirs.header_fields())
+        .filter(|(((field, _), _), _)| !DERIVED_FIELDS.contains(field));
+    for (((field, base_value), (_, our_value)), (_, their_value)) in fields {
+        if our_value != base_value {
+            copy_field(&mut header, ours, field);
+            if their_value != base_value && their_value != our_value {
+                header_conflicts.push(field);
+            }
+        } else if their_value != base_value {
+            copy_field(&mut header, theirs, field);
+        }
+    }
+    // Both sides add rerecords to the base, so keep the larger count rather than reporting a conflict
+    header.rerecord_count = ours.rerecord_count.max(theirs.rerecord_count);
+    header_conflicts.retain(|&field| field != "rerecord_count");
+
+    let mut conflicts = Vec::new();
+    let chunks: [Vec<Chunk>; 4] = std::array::from_fn(|controller| {
+        merge_inputs(
+            controller,
+            &base.inputs[controller],
+            &ours.inputs[controller],
+            &theirs.inputs[controller],
+            &mut conflicts,
+        )
+    });
+    Ok(Merge { header, header_conflicts, chunks, conflicts })
+}
+
+fn changes(side: Side, base: &[Input], new: &[Input]) -> Vec<Change> {
+    diff_inputs(base, new).into_iter()
+        .map(|hunk| Change { side, base: hunk.old, inputs: new[hunk.new].to_vec() })
+        .collect()
+}
+
+fn merge_inputs(controller: usize, base: &[Input], ours: &[Input], theirs: &[Input], conflicts: &mut Vec<Conflict>) -> Vec<Chunk> {
+    let mut all = changes(Side::Ours, base, ours);
+    all.extend(changes(Side::Theirs, base, theirs));
+    all.sort_by_key(|change| (change.base.start, change.base.end));
+
+    let mut chunks = Vec::new();
+    let mut position = 0;
+    let mut changes = all.into_iter().peekable();
+    while let Some(first) = changes.next() {
+        // Collect the changes that touch the same base frames. Insertions at the edge of
+        // another change are grouped with it, as their order would be ambiguous.
+        let mut group = vec![first];
+        let mut end = group[0].base.end;
+        while let Some(next) = changes.next_if(|next| {
+            next.base.start < end || (next.base.start == end && (next.base.is_empty() || group.last().unwrap().base.is_empty()))
+        }) {
+            end = end.max(next.base.end);
+            group.push(next);
+        }
+        let start = group[0].base.start;
+
+        chunks.push(Chunk::Clean(base[position..start].to_vec()));
+        let region = start..end;
+        let our_version = apply(base, &region, group.iter().filter(|change| change.side == Side::Ours));
+        let their_version = apply(base, &region, group.iter().filter(|change| change.side == Side::Theirs));
+        let both = group.iter().any(|change| change.side == Side::Ours) && group.iter().any(|change| change.side == Side::Theirs);
+        if !both || our_version == their_version {
+            let only_theirs = group.iter().all(|change| change.side == Side::Theirs);
+            chunks.push(Chunk::Clean(if only_theirs { their_version } else { our_version }));
+        } else {
+            chunks.push(Chunk::Conflict(conflicts.len()));
+            conflicts.push(Conflict { controller, base: region, ours: our_version, theirs: their_version, resolution: None });
+        }
+        position = end;
+    }
+    chunks.push(Chunk::Clean(base[position..].to_vec()));
+    chunks
+}
+
+/// The base frames in `region` with the changes of one side applied.
+fn apply<'a>(base: &[Input], region: &Range<usize>, changes: impl Iterator<Item = &'a Change>) -> Vec<Input> {
+    let mut result = Vec::new();
+    let mut position = region.start;
+    for change in changes {
+        result.extend_from_slice(&base[position..change.base.start]);
+        result.extend_from_slice(&change.inputs);
+        position = change.base.end;
+    }
+    result.extend_from_slice(&base[position..region.end]);
+    result
+}
+
+fn copy_field(dst: &mut M64File, src: &M64File, field: &str) {
+    match field {
+        "signature" => dst.signature = src.signature,
+        "version" => dst.version = src.version,
+        "uid" => dst.uid = src.uid,
+        "vi_count" => dst.vi_count = src.vi_count,
+        "rerecord_count" => dst.rerecord_count = src.rerecord_count,
+        "vi_per_second" => dst.vi_per_second = src.vi_per_second,
+        "controller_count" => dst.controller_count = src.controller_count,
+        "num_samples" => dst.num_samples = src.num_samples,
+        "movie_start_type" => dst.movie_start_type = src.movie_start_type,
+        "controller_flags" => dst.controller_flags = src.controller_flags,
+        "internal_name" => dst.internal_name = src.internal_name,
+        "crc32" => dst.crc32 = src.crc32,
+        "country_code" => dst.country_code = src.country_code,
+        "video_plugin" => dst.video_plugin = src.video_plugin,
+        "sound_plugin" => dst.sound_plugin = src.sound_plugin,
+        "input_plugin" => dst.input_plugin = src.input_plugin,
+        "rsp_plugin" => dst.rsp_plugin = src.rsp_plugin,
+        "author" => dst.author = src.author,
+        "movie_desc" => dst.movie_desc = src.movie_desc,
+        _ => {}
+    }
+}
+
+impl Merge {
+    pub fn resolve(&mut self, conflict: usize, side: Side) {
+        if let Some(conflict) = self.conflicts.get_mut(conflict) {
+            conflict.resolution = Some(side);
+        }
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.conflicts.iter().all(|conflict| conflict.resolution.is_some())
+    }
+
+    /// Builds the merged movie, using our version for unresolved conflicts.
+    pub fn to_movie(&self) -> M64File {
+        let mut movie = self.header.clone();
+        for (controller, chunks) in self.chunks.iter().enumerate() {
+            for chunk in chunks {
+                match chunk {
+                    Chunk::Clean(inputs) => movie.inputs[controller].extend_from_slice(inputs),
+                    Chunk::Conflict(i) => movie.inputs[controller].extend_from_slice(self.conflicts[*i].resolved()),
+                }
+            }
+        }
+        // Conflicts resolved to versions of different lengths can leave the controllers uneven
+        let frames = movie.inputs.iter().map(Vec::len).max().unwrap_or(0);
+        for i in M64File::active_controllers(movie.controller_flags).unwrap_or_default() {
+            movie.inputs[i].resize(frames, Input::new());
+        }
+        // The header still has the base's lengths, which give its VIs per frame
+        movie.vi_count = Timing::of(&self.header).frame_to_vi(frames) as u32;
+        movie.num_samples = frames as u32;
+        movie
+    }
+
+    /// The merged movie in the text form of [`movie_text`], with each unresolved conflict
+    /// between git-style conflict markers and a comment for each header conflict.
+    pub fn to_text(&self) -> String {
+        let movie = self.to_movie();
+        let mut text = movie_text::encode_header(&movie);
+        for field in &self.header_conflicts {
+            writeln!(text, "# Both sides changed {}, ours is kept", field).unwrap();
+        }
+        let active = M64File::active_controllers(movie.controller_flags).unwrap_or_default();
+        for (controller, chunks) in self.chunks.iter().enumerate() {
+            if movie.inputs[controller].is_empty() && !active.contains(&controller) {
+                continue;
+            }
+            writeln!(text, "\n[controller {}]", controller + 1).unwrap();
+            text.push_str("#    X    Y Buttons\n");
+            for chunk in chunks {
+                match chunk {
+                    Chunk::Clean(inputs) => push_lines(&mut text, inputs),
+                    Chunk::Conflict(i) if self.conflicts[*i].resolution.is_none() => {
+                        let conflict = &self.conflicts[*i];
+                        text.push_str("<<<<<<< ours\n");
+                        push_lines(&mut text, &conflict.ours);
+                        text.push_str("=======\n");
+                        push_lines(&mut text, &conflict.theirs);
+                        text.push_str(">>>>>>> theirs\n");
+                    }
+                    Chunk::Conflict(i) => push_lines(&mut text, self.conflicts[*i].resolved()),
+                }
+            }
+        }
+        text
+    }
+}
+
+fn push_lines(text: &mut String, inputs: &[Input]) {
+    for input in inputs {
+        writeln!(text, "{}", input).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::m64_handling::{string_to_ascii, test_movie};
+
+    #[test]
+    fn separate_changes_merge_cleanly() {
+        let base = test_movie(60, 0b0001);
+        let mut ours = base.clone();
+        ours.inputs[0][5].b_button = true;
+        ours.inputs[0].splice(20..20, vec![Input::new(); 2]);
+        ours.num_samples = 62;
+        ours.rerecord_count = 4;
+        let mut theirs = base.clone();
+        theirs.inputs[0][50].z_trig = true;
+        theirs.rerecord_count = 9;
+        theirs.author = string_to_ascii("them").unwrap();
+        let merge = merge(&base, &ours, &theirs).unwrap();
+        assert!(merge.conflicts.is_empty() && merge.header_conflicts.is_empty());
+        let movie = merge.to_movie();
+        assert_eq!(movie.inputs[0].len(), 62);
+        assert!(movie.inputs[0][5].b_button && movie.inputs[0][52].z_trig);
+        assert_eq!(movie.rerecord_count, 9);
+        assert_eq!(movie.author, theirs.author);
+        assert!(movie.validate().is_empty(), "{:?}", movie.validate());
+    }
+
+    #[test]
+    fn length_changes_on_both_sides_dont_conflict() {
+        let base = test_movie(60, 0b0001);
+        let mut ours = base.clone();
+        ours.inputs[0].splice(10..10, vec![Input::new(); 4]);
+        ours.num_samples = 64;
+        ours.vi_count = 128;
+        let mut theirs = base.clone();
+        theirs.inputs[0].splice(50..50, vec![Input::new(); 6]);
+        theirs.num_samples = 66;
+        theirs.vi_count = 132;
+        let merge = merge(&base, &ours, &theirs).unwrap();
+        assert!(merge.header_conflicts.is_empty(), "{:?}", merge.header_conflicts);
+        assert!(merge.conflicts.is_empty());
+        let movie = merge.to_movie();
+        assert_eq!((movie.num_samples, movie.vi_count), (70, 140));
+    }
+
+    #[test]
+    fn overlapping_changes_conflict() {
+        let base = test_movie(30, 0b0001);
+        let mut ours = base.clone();
+        ours.inputs[0][10].start = true;
+        ours.uid = 1;
+        let mut theirs = base.clone();
+        theirs.inputs[0][10].l_trig = true;
+        theirs.uid = 2;
+        let mut merge = merge(&base, &ours, &theirs).unwrap();
+        assert_eq!(merge.header_conflicts, vec!["uid"]);
+        assert_eq!(merge.conflicts.len(), 1);
+        assert_eq!(merge.conflicts[0].base, 10..11);
+        assert!(merge.to_movie().inputs[0][10].start);
+        assert!(merge.to_text().contains("<<<<<<< ours"));
+        merge.resolve(0, Side::Theirs);
+        assert!(merge.is_resolved());
+        assert!(merge.to_movie().inputs[0][10].l_trig);
+        assert_eq!(merge.to_movie().uid, 1);
+    }
+
+    #[test]
+    fn identical_changes_dont_conflict() {
+        let base = test_movie(30, 0b0001);
+        let mut ours = base.clone();
+        ours.inputs[0][3].c_down = true;
+        let merge = merge(&base, &ours, &ours.clone()).unwrap();
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(merge.to_movie().inputs, ours.inputs);
+    }
+}
@@ -1,2 +1,20 @@
+pub mod batch;
+pub mod bk2;
+pub mod diff;
+pub mod expression;
 pub mod file_handling;
-pub mod m64_handling;
\ No newline at end of file
+pub mod format;
+pub mod history;
+pub mod input_text;
+pub mod journal;
+pub mod m64_handling;
+pub mod merge;
+pub mod movie_text;
+pub mod patterns;
+pub mod rom;
+pub mod scripting;
+pub mod search;
+pub mod stats;
+pub mod stick;
+pub mod table;
+pub mod timecode;
\ No newline at end of file
@@ -0,0 +1,202 @@
+use crate::api::input_text;
+use crate::api::m64_handling::{Input, M64Error, M64File};
+use anyhow::Result;
+use std::ascii::Char as AsciiChar;
+use std::fmt::Write;
+
+// Text form of a whole movie: the header as one "field value" line per field, then a section
+// per controller with one line per frame in the input_text format, e.g.
+//   version 3
+//   author "someone"
+//   [controller 1]
+//      0  127 A
+// Frames aren't numbered, so inserting one only adds a line to a diff. Text fields are quoted,
+// with '"', '\' and bytes outside printable ASCII escaped, and trailing NULs left out.
+
+const HEADER_LINE: &str = "# m64 movie";
+
+fn quote(chars: &[AsciiChar]) -> String {
+    let end = chars.iter().rposition(|c| c.to_u8() != 0).map_or(0, |i| i + 1);
+    let mut text = String::from("\"");
+    for c in &chars[..end] {
+        match c.to_u8() {
+            b'"' => text.push_str("\\\""),
+            b'\\' => text.push_str("\\\\"),
+            b @ 0x20..=0x7E => text.push(b as char),
+            b => write!(text, "\\x{:02X}", b).unwrap(),
+        }
+    }
+    text.push('"');
+    text
+}
+
+fn unquote<const N: usize>(value: &str) -> Result<[AsciiChar; N], String> {
+    let inner = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).ok_or("expected a quoted string")?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('x') => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    bytes.push(u8::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape \"\\x{}\"", hex))?);
+                }
+                Some(c @ ('"' | '\\')) => bytes.push(c as u8),
+                _ => return Err("invalid escape".to_string()),
+            },
+            c if c.is_ascii() => bytes.push(c as u8),
+            c => return Err(format!("'{}' is not ASCII", c)),
+        }
+    }
+    if bytes.len() > N {
+        return Err(format!("longer than {} characters", N));
+    }
+    bytes.resize(N, 0);
+    Ok(*<[u8; N]>::try_from(bytes).unwrap().as_ascii().ok_or("not ASCII")?)
+}
+
+fn parse_hex<T: TryFrom<u64>>(value: &str) -> Result<T, String> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    u64::from_str_radix(digits, 16).ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or(format!("invalid hexadecimal number \"{}\"", value))
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid number \"{}\"", value))
+}
+
+/// Each header field with its value as written in the text form.
+pub(crate) fn header_lines(movie: &M64File) -> Vec<(&'static str, String)> {
+    vec![
+        ("signature", format!("{:08X}", u32::from_be_bytes(movie.signature))),
+        ("version", movie.version.to_string()),
+        ("uid", movie.uid.to_string()),
+        ("vi_count", movie.vi_count.to_string()),
+        ("rerecord_count", movie.rerecord_count.to_string()),
+        ("vi_per_second", movie.vi_per_second.to_string()),
+        ("controller_count", movie.controller_count.to_string()),
+        ("num_samples", movie.num_samples.to_string()),
+        ("movie_start_type", movie.movie_start_type.to_string()),
+        ("controller_flags", format!("{:#06X}", movie.controller_flags)),
+        ("internal_name", quote(&movie.internal_name)),
+        ("crc32", format!("{:08X}", movie.crc32)),
+        ("country_code", format!("{:#06X}", movie.country_code)),
+        ("video_plugin", quote(&movie.video_plugin)),
+        ("sound_plugin", quote(&movie.sound_plugin)),
+        ("input_plugin", quote(&movie.input_plugin)),
+        ("rsp_plugin", quote(&movie.rsp_plugin)),
+        ("author", quote(&movie.author)),
+        ("movie_desc", quote(&movie.movie_desc)),
+    ]
+}
+
+/// The header line and header fields, without any inputs.
+pub(crate) fn encode_header(movie: &M64File) -> String {
+    let mut text = format!("{}\n", HEADER_LINE);
+    for (field, value) in header_lines(movie) {
+        writeln!(text, "{} {}", field, value).unwrap();
+    }
+    text
+}
+
+pub fn encode(movie: &M64File) -> String {
+    let mut text = encode_header(movie);
+    let active = M64File::active_controllers(movie.controller_flags).unwrap_or_default();
+    for (controller, inputs) in movie.inputs.iter().enumerate() {
+        // Inactive controllers normally have no inputs, but are written if they do to stay lossless
+        if inputs.is_empty() && !active.contains(&controller) {
+            continue;
+        }
+        writeln!(text, "\n[controller {}]", controller + 1).unwrap();
+        text.push_str(&input_text::encode(inputs));
+    }
+    text
+}
+
+pub(crate) fn set_field(movie: &mut M64File, field: &str, value: &str) -> Result<(), String> {
+    match field {
+        "signature" => movie.signature = parse_hex::<u32>(value)?.to_be_bytes(),
+        "version" => movie.version = parse_number(value)?,
+        "uid" => movie.uid = parse_number(value)?,
+        "vi_count" => movie.vi_count = parse_number(value)?,
+        "rerecord_count" => movie.rerecord_count = parse_number(value)?,
+        "vi_per_second" => movie.vi_per_second = parse_number(value)?,
+        "controller_count" => movie.controller_count = parse_number(value)?,
+        "num_samples" => movie.num_samples = parse_number(value)?,
+        "movie_start_type" => movie.movie_start_type = parse_number(value)?,
+        "controller_flags" => movie.controller_flags = parse_hex(value)?,
+        "internal_name" => movie.internal_name = unquote(value)?,
+        "crc32" => movie.crc32 = parse_hex(value)?,
+        "country_code" => movie.country_code = parse_hex(value)?,
+        "video_plugin" => movie.video_plugin = unquote(value)?,
+        "sound_plugin" => movie.sound_plugin = unquote(value)?,
+        "input_plugin" => movie.input_plugin = unquote(value)?,
+        "rsp_plugin" => movie.rsp_plugin = unquote(value)?,
+        "author" => movie.author = unquote(value)?,
+        "movie_desc" => movie.movie_desc = unquote(value)?,
+        _ => return Err(format!("unknown header field \"{}\"", field)),
+    }
+    Ok(())
+}
+
+/// Reads a movie written by [`encode`]. Header fields that are left out keep their defaults.
+pub fn decode(text: &str) -> Result<M64File> {
+    let mut movie = M64File::new();
+    let mut controller: Option<usize> = None;
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: String| M64Error { message: format!("Line {}: {}", i + 1, message) };
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some(section) = trimmed.strip_prefix("[controller ").and_then(|s| s.strip_suffix(']')) {
+            match section.parse::<usize>() {
+                Ok(port @ 1..=4) => controller = Some(port - 1),
+                _ => return Err(error(format!("invalid controller \"{}\"", section)).into()),
+            }
+            continue;
+        }
+        match controller {
+            Some(controller) => {
+                let input: Input = line.parse().map_err(|e| error(format!("{}", e)))?;
+                movie.inputs[controller].push(input);
+            }
+            None => {
+                let (field, value) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+                set_field(&mut movie, field, value.trim()).map_err(error)?;
+            }
+        }
+    }
+    Ok(movie)
+}
+
+/// Whether `data` looks like a movie written by [`encode`] rather than a binary m64.
+pub fn is_movie_text(data: &[u8]) -> bool {
+    data.starts_with(HEADER_LINE.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::m64_handling::{string_to_ascii, test_movie};
+
+    #[test]
+    fn round_trip() {
+        let mut movie = test_movie(25, 0b1001 | 0x80);
+        movie.uid = -7;
+        movie.crc32 = 0xDEADBEEF;
+        movie.author = string_to_ascii("tab\there \"quoted\" \\ back").unwrap();
+        movie.movie_desc[200] = 0x7F_u8.as_ascii().unwrap();
+        movie.inputs[0][1].z_trig = true;
+        movie.inputs[3][24].c_up = true;
+        let text = encode(&movie);
+        assert!(is_movie_text(text.as_bytes()));
+        assert_eq!(decode(&text).unwrap().to_bytes().unwrap(), movie.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn binary_movies_are_not_text() {
+        assert!(!is_movie_text(&test_movie(5, 0b0001).to_bytes().unwrap()));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::api::m64_handling::{Button, Input};
+use crate::api::stick;
+
+// Fill operations over a range of frames. They work on slices so they can be used on
+// any part of a controller, and through `Edit::replace` when the change should be undoable.
+
+pub fn hold(inputs: &mut [Input], button: Button) {
+    inputs.iter_mut().for_each(|input| input.set(button, true));
+}
+
+pub fn release(inputs: &mut [Input], button: Button) {
+    inputs.iter_mut().for_each(|input| input.set(button, false));
+}
+
+/// Presses `button` for `period` frames, then releases it for `period` frames, starting pressed.
+pub fn mash(inputs: &mut [Input], button: Button, period: usize) {
+    let period = period.max(1);
+    for (i, input) in inputs.iter_mut().enumerate() {
+        input.set(button, (i / period).is_multiple_of(2));
+    }
+}
+
+pub fn set_stick(inputs: &mut [Input], x: i8, y: i8) {
+    for input in inputs {
+        input.x = x;
+        input.y = y;
+    }
+}
+
+/// Moves the stick linearly from `from` on the first frame to `to` on the last frame.
+pub fn interpolate_stick(inputs: &mut [Input], from: (i8, i8), to: (i8, i8)) {
+    let steps = inputs.len().saturating_sub(1).max(1) as f64;
+    for (i, input) in inputs.iter_mut().enumerate() {
+        let t = i as f64 / steps;
+        input.x = stick::to_raw(from.0 as f64 + (to.0 as f64 - from.0 as f64) * t);
+        input.y = stick::to_raw(from.1 as f64 + (to.1 as f64 - from.1 as f64) * t);
+    }
+}
+
+/// Interpolates between the stick values of the first and last frame of the range.
+pub fn interpolate_keyframes(inputs: &mut [Input]) {
+    if let (Some(first), Some(last)) = (inputs.first(), inputs.last()) {
+        let (from, to) = ((first.x, first.y), (last.x, last.y));
+        interpolate_stick(inputs, from, to);
+    }
+}
+
+/// Fills the range by repeating `pattern` from its first frame.
+pub fn repeat(inputs: &mut [Input], pattern: &[Input]) {
+    if pattern.is_empty() {
+        return;
+    }
+    for (input, frame) in inputs.iter_mut().zip(pattern.iter().cycle()) {
+        *input = frame.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pressed(inputs: &[Input]) -> Vec<bool> {
+        inputs.iter().map(|input| input.a_button).collect()
+    }
+
+    #[test]
+    fn mash_alternates_by_period() {
+        let mut inputs = vec![Input::new(); 6];
+        mash(&mut inputs, Button::A, 2);
+        assert_eq!(pressed(&inputs), [true, true, false, false, true, true]);
+        // A period of 0 is taken as 1 rather than dividing by zero
+        mash(&mut inputs, Button::A, 0);
+        assert_eq!(pressed(&inputs), [true, false, true, false, true, false]);
+        release(&mut inputs[..3], Button::A);
+        hold(&mut inputs[3..4], Button::A);
+        assert_eq!(pressed(&inputs), [false, false, false, true, true, false]);
+    }
+
+    #[test]
+    fn interpolation_hits_both_ends() {
+        let mut inputs = vec![Input::new(); 5];
+        interpolate_stick(&mut inputs, (-120, 0), (120, 40));
+        let sticks: Vec<(i8, i8)> = inputs.iter().map(|input| (input.x, input.y)).collect();
+        assert_eq!(sticks, [(-120, 0), (-60, 10), (0, 20), (60, 30), (120, 40)]);
+
+        let mut single = vec![Input { x: 5, y: 5, ..Input::new() }];
+        interpolate_keyframes(&mut single);
+        assert_eq!((single[0].x, single[0].y), (5, 5));
+        interpolate_keyframes(&mut []);
+    }
+
+    #[test]
+    fn repeat_cycles_the_pattern() {
+        let pattern = [Input { a_button: true, ..Input::new() }, Input::new()];
+        let mut inputs = vec![Input::new(); 5];
+        repeat(&mut inputs, &pattern);
+        assert_eq!(pressed(&inputs), [true, false, true, false, true]);
+        repeat(&mut inputs, &[]);
+        assert_eq!(pressed(&inputs), [true, false, true, false, true]);
+    }
+}
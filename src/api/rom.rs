@@ -0,0 +1,187 @@
+use crate::api::file_handling::read_file;
+use crate::api::m64_handling::{ascii_to_string, string_to_ascii, M64Error, M64File};
+use anyhow::Result;
+use std::path::Path;
+
+const HEADER_SIZE: usize = 0x40;
+
+/// Byte order of a ROM dump, named after the usual file extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RomFormat {
+    // Big-endian, as on the cartridge
+    Z64,
+    // Bytes swapped in each 16-bit word
+    V64,
+    // Little-endian 32-bit words
+    N64,
+}
+
+impl RomFormat {
+    pub fn detect(data: &[u8]) -> Option<RomFormat> {
+        match data.get(..4)? {
+            [0x80, 0x37, 0x12, 0x40] => Some(RomFormat::Z64),
+            [0x37, 0x80, 0x40, 0x12] => Some(RomFormat::V64),
+            [0x40, 0x12, 0x37, 0x80] => Some(RomFormat::N64),
+            _ => None,
+        }
+    }
+}
+
+/// Detects the byte order of `data` and converts it to big-endian in place.
+pub fn to_z64(data: &mut [u8]) -> Result<RomFormat> {
+    let format = RomFormat::detect(data).ok_or(M64Error { message: "Not an N64 ROM".to_string() })?;
+    match format {
+        RomFormat::Z64 => {}
+        RomFormat::V64 => data.chunks_exact_mut(2).for_each(|word| word.swap(0, 1)),
+        RomFormat::N64 => data.chunks_exact_mut(4).for_each(|word| word.reverse()),
+    }
+    Ok(format)
+}
+
+/// The parts of a ROM header that a movie records.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rom {
+    pub format: RomFormat,
+    pub name: String,
+    pub crc1: u32,
+    pub crc2: u32,
+    pub country_code: u8,
+}
+
+impl Rom {
+    pub fn from_bytes(data: &[u8]) -> Result<Rom> {
+        if data.len() < HEADER_SIZE {
+            return Err(M64Error { message: "ROM is too small".to_string() }.into());
+        }
+        let mut header = data[..HEADER_SIZE].to_vec();
+        let format = to_z64(&mut header)?;
+        // The name is padded with spaces, and some dumps use other non-ASCII padding
+        let name = header[0x20..0x34].iter()
+            .map(|&b| if b.is_ascii() { b as char } else { ' ' })
+            .collect::<String>()
+            .trim_end_matches([' ', '\0'])
+            .to_string();
+        Ok(Rom {
+            format,
+            name,
+            crc1: u32::from_be_bytes(header[0x10..0x14].try_into()?),
+            crc2: u32::from_be_bytes(header[0x14..0x18].try_into()?),
+            country_code: header[0x3E],
+        })
+    }
+
+    pub fn open(path: &Path) -> Result<Rom> {
+        Self::from_bytes(&read_file(path)?)
+    }
+
+    /// Sets the ROM fields of the movie header to this ROM.
+    pub fn fill_header(&self, movie: &mut M64File) -> Result<()> {
+        movie.internal_name = string_to_ascii(&self.name)?;
+        movie.crc32 = self.crc1;
+        movie.country_code = self.country_code as u16;
+        Ok(())
+    }
+
+    /// Describes each ROM field of the movie header that doesn't match this ROM.
+    pub fn check_header(&self, movie: &M64File) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let name = ascii_to_string(&movie.internal_name);
+        if name.trim_end() != self.name {
+            warnings.push(format!("Movie was recorded on \"{}\", but the ROM is \"{}\"", name.trim_end(), self.name));
+        }
+        if movie.crc32 != self.crc1 {
+            warnings.push(format!("Movie CRC {:08X} doesn't match the ROM CRC {:08X}", movie.crc32, self.crc1));
+        }
+        if movie.country_code != self.country_code as u16 {
+            warnings.push(format!(
+                "Movie country code {} doesn't match the ROM country code {}",
+                country_name(movie.country_code as u8),
+                country_name(self.country_code)
+            ));
+        }
+        warnings
+    }
+}
+
+pub fn country_name(code: u8) -> String {
+    let name = match code {
+        b'A' => "Asia",
+        b'B' => "Brazil",
+        b'C' => "China",
+        b'D' => "Germany",
+        b'E' => "USA",
+        b'F' => "France",
+        b'I' => "Italy",
+        b'J' => "Japan",
+        b'P' | b'X' | b'Y' => "Europe",
+        b'S' => "Spain",
+        b'U' => "Australia",
+        _ => return format!("{:#04X}", code),
+    };
+    format!("{} ({})", name, code as char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::m64_handling::test_movie;
+
+    fn z64_header() -> Vec<u8> {
+        let mut header = vec![0_u8; HEADER_SIZE];
+        header[..4].copy_from_slice(&[0x80, 0x37, 0x12, 0x40]);
+        header[0x10..0x14].copy_from_slice(&0x635A2BFF_u32.to_be_bytes());
+        header[0x14..0x18].copy_from_slice(&0x8B022326_u32.to_be_bytes());
+        header[0x20..0x34].copy_from_slice(b"SUPER MARIO 64      ");
+        header[0x3E] = b'E';
+        header
+    }
+
+    #[test]
+    fn byte_orders_read_the_same() {
+        let z64 = z64_header();
+        let mut v64 = z64.clone();
+        v64.chunks_exact_mut(2).for_each(|word| word.swap(0, 1));
+        let mut n64 = z64.clone();
+        n64.chunks_exact_mut(4).for_each(|word| word.reverse());
+
+        let rom = Rom::from_bytes(&z64).unwrap();
+        assert_eq!(rom, Rom {
+            format: RomFormat::Z64,
+            name: "SUPER MARIO 64".to_string(),
+            crc1: 0x635A2BFF,
+            crc2: 0x8B022326,
+            country_code: b'E',
+        });
+        assert_eq!(Rom::from_bytes(&v64).unwrap(), Rom { format: RomFormat::V64, ..rom.clone() });
+        assert_eq!(Rom::from_bytes(&n64).unwrap(), Rom { format: RomFormat::N64, ..rom });
+
+        for mut swapped in [v64, n64] {
+            to_z64(&mut swapped).unwrap();
+            assert_eq!(swapped, z64);
+        }
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        assert!(Rom::from_bytes(&z64_header()[..HEADER_SIZE - 1]).is_err());
+        let mut header = z64_header();
+        header[0] = 0;
+        assert!(Rom::from_bytes(&header).is_err());
+        assert_eq!(RomFormat::detect(&[0x80, 0x37]), None);
+    }
+
+    #[test]
+    fn header_check_lists_mismatches() {
+        let rom = Rom::from_bytes(&z64_header()).unwrap();
+        let mut movie = test_movie(1, 0b0001);
+        rom.fill_header(&mut movie).unwrap();
+        assert!(rom.check_header(&movie).is_empty());
+        movie.country_code = b'J' as u16;
+        movie.crc32 = 0;
+        assert_eq!(rom.check_header(&movie), [
+            "Movie CRC 00000000 doesn't match the ROM CRC 635A2BFF",
+            "Movie country code Japan (J) doesn't match the ROM country code USA (E)",
+        ]);
+        assert_eq!(country_name(b'Z'), "0x5A");
+    }
+}
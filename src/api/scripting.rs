@@ -0,0 +1,216 @@
+use crate::api::m64_handling::{ascii_to_string, string_to_ascii, Button, Input, M64Error, M64File};
+use anyhow::Result;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Position, INT};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Limits that keep a runaway script from hanging the editor or exhausting memory
+const MAX_OPERATIONS: u64 = 100_000_000;
+const MAX_ARRAY_SIZE: usize = 1_000_000;
+const MAX_STRING_SIZE: usize = 1_000_000;
+
+// Script names of the Input fields, in the order of Button::ALL
+const BUTTON_FIELDS: [&str; 14] = [
+    "d_right", "d_left", "d_down", "d_up", "start", "z", "b", "a",
+    "c_right", "c_left", "c_down", "c_up", "r", "l",
+];
+
+/// The movie as seen by a script, shared with the engine as the `movie` variable.
+#[derive(Clone)]
+struct Movie(Rc<RefCell<M64File>>);
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn script_error<T>(message: impl ToString) -> ScriptResult<T> {
+    Err(Box::new(EvalAltResult::ErrorRuntime(message.to_string().into(), Position::NONE)))
+}
+
+impl Movie {
+    fn check(&self, controller: INT, frame: INT) -> ScriptResult<(usize, usize)> {
+        let movie = self.0.borrow();
+        if !(0..4).contains(&controller) {
+            return script_error(format!("Controller {} does not exist", controller));
+        }
+        let frames = movie.inputs[controller as usize].len();
+        if frame < 0 || frame as usize >= frames {
+            return script_error(format!("Frame {} is out of range for controller {} with {} frames", frame, controller, frames));
+        }
+        Ok((controller as usize, frame as usize))
+    }
+
+    fn input(&mut self, controller: INT, frame: INT) -> ScriptResult<Input> {
+        let (controller, frame) = self.check(controller, frame)?;
+        Ok(self.0.borrow().inputs[controller][frame].clone())
+    }
+
+    fn set_input(&mut self, controller: INT, frame: INT, input: Input) -> ScriptResult<()> {
+        let (controller, frame) = self.check(controller, frame)?;
+        self.0.borrow_mut().inputs[controller][frame] = input;
+        Ok(())
+    }
+
+    fn insert(&mut self, frame: INT, count: INT) -> ScriptResult<()> {
+        let frames = self.frames();
+        if frame < 0 || frame > frames || count < 0 {
+            return script_error(format!("Cannot insert {} frames at frame {}", count, frame));
+        }
+        let range = frame as usize..(frame + count) as usize;
+        self.0.borrow_mut().add_inputs(&range).map(|_| ()).or_else(script_error)
+    }
+
+    fn remove(&mut self, frame: INT, count: INT) -> ScriptResult<()> {
+        let frames = self.frames();
+        if frame < 0 || count < 0 || frame + count > frames {
+            return script_error(format!("Cannot remove {} frames at frame {}", count, frame));
+        }
+        let range = frame as usize..(frame + count) as usize;
+        self.0.borrow_mut().remove_inputs(&range).map(|_| ()).or_else(script_error)
+    }
+
+    fn frames(&self) -> INT {
+        self.0.borrow().inputs.iter().map(Vec::len).max().unwrap_or(0) as INT
+    }
+
+    fn controllers(&mut self) -> ScriptResult<Array> {
+        let active = M64File::active_controllers(self.0.borrow().controller_flags).or_else(script_error)?;
+        Ok(active.into_iter().map(|controller| Dynamic::from(controller as INT)).collect())
+    }
+}
+
+fn register_input(engine: &mut Engine) {
+    engine.register_type_with_name::<Input>("Input")
+        .register_fn("input", Input::new)
+        .register_fn("to_string", |input: &mut Input| input.to_string())
+        .register_fn("==", |a: Input, b: Input| a == b)
+        .register_fn("!=", |a: Input, b: Input| a != b);
+    for (button, name) in Button::ALL.into_iter().zip(BUTTON_FIELDS) {
+        engine.register_get_set(
+            name,
+            move |input: &mut Input| input.pressed(button),
+            move |input: &mut Input, pressed: bool| input.set(button, pressed),
+        );
+    }
+    engine.register_get_set("x", |input: &mut Input| input.x as INT, |input: &mut Input, x: INT| -> ScriptResult<()> {
+        input.x = i8::try_from(x).or_else(|_| script_error(format!("Stick X {} is out of range", x)))?;
+        Ok(())
+    });
+    engine.register_get_set("y", |input: &mut Input| input.y as INT, |input: &mut Input, y: INT| -> ScriptResult<()> {
+        input.y = i8::try_from(y).or_else(|_| script_error(format!("Stick Y {} is out of range", y)))?;
+        Ok(())
+    });
+}
+
+fn register_movie(engine: &mut Engine) {
+    engine.register_type_with_name::<Movie>("Movie")
+        .register_fn("input", Movie::input)
+        .register_fn("set_input", Movie::set_input)
+        .register_fn("insert", Movie::insert)
+        .register_fn("remove", Movie::remove)
+        .register_fn("controllers", Movie::controllers)
+        .register_get("frames", |movie: &mut Movie| movie.frames());
+
+    macro_rules! number_field {
+        ($name:literal, $field:ident, $type:ty) => {
+            engine.register_get_set(
+                $name,
+                |movie: &mut Movie| movie.0.borrow().$field as INT,
+                |movie: &mut Movie, value: INT| -> ScriptResult<()> {
+                    let value = <$type>::try_from(value).or_else(|_| script_error(format!("{} is out of range for {}", value, $name)))?;
+                    movie.0.borrow_mut().$field = value;
+                    Ok(())
+                },
+            );
+        };
+    }
+    number_field!("uid", uid, i32);
+    number_field!("vi_count", vi_count, u32);
+    number_field!("rerecord_count", rerecord_count, u32);
+    number_field!("vi_per_second", vi_per_second, u8);
+    number_field!("movie_start_type", movie_start_type, u16);
+    number_field!("crc32", crc32, u32);
+    number_field!("country_code", country_code, u16);
+
+    macro_rules! text_field {
+        ($name:literal, $field:ident) => {
+            engine.register_get_set(
+                $name,
+                |movie: &mut Movie| ascii_to_string(&movie.0.borrow().$field),
+                |movie: &mut Movie, text: String| -> ScriptResult<()> {
+                    movie.0.borrow_mut().$field = string_to_ascii(&text).or_else(script_error)?;
+                    Ok(())
+                },
+            );
+        };
+    }
+    text_field!("internal_name", internal_name);
+    text_field!("video_plugin", video_plugin);
+    text_field!("sound_plugin", sound_plugin);
+    text_field!("input_plugin", input_plugin);
+    text_field!("rsp_plugin", rsp_plugin);
+    text_field!("author", author);
+    text_field!("description", movie_desc);
+}
+
+/// Runs a Rhai script with the movie bound to the `movie` variable, returning what it printed.
+///
+/// The script can read and change the header and inputs, but has no access to files or the
+/// rest of the editor. On error the movie is left as it was.
+pub fn run_script(script: &str, movie: &mut M64File) -> Result<String> {
+    let output = Rc::new(RefCell::new(String::new()));
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_array_size(MAX_ARRAY_SIZE);
+    engine.set_max_string_size(MAX_STRING_SIZE);
+    engine.disable_symbol("eval");
+    // Scripts are shared around, so they mustn't be able to load other files
+    engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+    let print_output = output.clone();
+    engine.on_print(move |text| {
+        print_output.borrow_mut().push_str(text);
+        print_output.borrow_mut().push('\n');
+    });
+    let debug_output = output.clone();
+    engine.on_debug(move |text, _, position| {
+        debug_output.borrow_mut().push_str(&format!("{:?}: {}\n", position, text));
+    });
+    register_input(&mut engine);
+    register_movie(&mut engine);
+
+    let shared = Movie(Rc::new(RefCell::new(movie.clone())));
+    let mut scope = rhai::Scope::new();
+    scope.push("movie", shared.clone());
+    engine.run_with_scope(&mut scope, script)
+        .map_err(|e| M64Error { message: format!("Script failed: {}", e) })?;
+    drop(scope);
+
+    let mut result = Rc::try_unwrap(shared.0).map(RefCell::into_inner).unwrap_or_else(|shared| shared.borrow().clone());
+    let frames = result.inputs.iter().map(Vec::len).max().unwrap_or(0);
+    result.num_samples = frames as u32;
+    *movie = result;
+    Ok(output.take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::file_handling::TempDir;
+    use crate::api::m64_handling::test_movie;
+
+    #[test]
+    fn scripts_edit_the_movie() {
+        let mut movie = test_movie(5, 0b0001);
+        let script = "let input = movie.input(0, 4); input.x = -20; movie.set_input(0, 4, input); print(movie.input(0, 4).x);";
+        let output = run_script(script, &mut movie).unwrap();
+        assert_eq!(output, "-20\n");
+        assert_eq!(movie.inputs[0][4].x, -20);
+    }
+
+    #[test]
+    fn imports_are_refused() {
+        let mut movie = test_movie(5, 0b0001);
+        let directory = TempDir::new("script");
+        std::fs::write(directory.join("module.rhai"), "export const X = 1;").unwrap();
+        let script = format!("import \"{}\" as m;", directory.join("module").display());
+        assert!(run_script(&script, &mut movie).is_err());
+    }
+}
@@ -0,0 +1,402 @@
+use crate::api::m64_handling::{Button, Input, M64Error};
+use crate::api::stick;
+use anyhow::Result;
+use bitvec::prelude::BitVec;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value {
+    X,
+    Y,
+    Magnitude,
+    Angle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+/// A condition on the inputs of a frame, or on a run of frames for `HeldFor`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Predicate {
+    Pressed(Button),
+    Compare(Value, Comparison, f64),
+    Not(Box<Predicate>),
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+    // Frames in runs of at least this many consecutive matching frames
+    HeldFor(Box<Predicate>, usize),
+}
+
+impl Value {
+    pub fn of(&self, input: &Input) -> f64 {
+        match self {
+            Value::X => input.x as f64,
+            Value::Y => input.y as f64,
+            Value::Magnitude => stick::magnitude(input.x, input.y),
+            Value::Angle => stick::angle(input.x, input.y),
+        }
+    }
+}
+
+impl Comparison {
+    pub fn holds(&self, a: f64, b: f64) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessEqual => a <= b,
+            Comparison::Greater => a > b,
+            Comparison::GreaterEqual => a >= b,
+            Comparison::Equal => a == b,
+            Comparison::NotEqual => a != b,
+        }
+    }
+}
+
+impl Predicate {
+    /// Which frames of `inputs` match.
+    pub fn evaluate(&self, inputs: &[Input]) -> BitVec {
+        match self {
+            Predicate::Pressed(button) => inputs.iter().map(|input| input.pressed(*button)).collect(),
+            Predicate::Compare(value, comparison, operand) => {
+                inputs.iter().map(|input| comparison.holds(value.of(input), *operand)).collect()
+            }
+            Predicate::Not(predicate) => !predicate.evaluate(inputs),
+            Predicate::And(a, b) => a.evaluate(inputs) & b.evaluate(inputs),
+            Predicate::Or(a, b) => a.evaluate(inputs) | b.evaluate(inputs),
+            Predicate::HeldFor(predicate, frames) => {
+                let mut matches = predicate.evaluate(inputs);
+                for run in runs(&matches.clone()) {
+                    if run.len() < *frames {
+                        matches[run].fill(false);
+                    }
+                }
+                matches
+            }
+        }
+    }
+}
+
+pub(crate) fn runs(matches: &BitVec) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut start = None;
+    for (i, matched) in matches.iter().by_vals().chain([false]).enumerate() {
+        match (matched, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                runs.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    runs
+}
+
+/// Every run of consecutive frames matching `predicate`.
+pub fn find(inputs: &[Input], predicate: &Predicate) -> Vec<Range<usize>> {
+    runs(&predicate.evaluate(inputs))
+}
+
+pub fn first(inputs: &[Input], predicate: &Predicate) -> Option<usize> {
+    predicate.evaluate(inputs).first_one()
+}
+
+/// Start of the first matching range after `frame`.
+pub fn next(inputs: &[Input], predicate: &Predicate, frame: usize) -> Option<usize> {
+    find(inputs, predicate).into_iter().map(|range| range.start).find(|&start| start > frame)
+}
+
+/// Start of the last matching range before `frame`.
+pub fn previous(inputs: &[Input], predicate: &Predicate, frame: usize) -> Option<usize> {
+    find(inputs, predicate).into_iter().rev().map(|range| range.start).find(|&start| start < frame)
+}
+
+// Text syntax, e.g. "A && !B", "|stick| > 100" or "held(Z, 3)":
+//   or:         and ("||" and)*
+//   and:        unary ("&&" unary)*
+//   unary:      "!" unary | "(" or ")" | "held" "(" or "," number ")" | value comparison number | button
+//   value:      "x" | "y" | "|stick|" | "angle"
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Token {
+    Name(String),
+    Number(f64),
+    Symbol(&'static str),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "\"{}\"", name),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Symbol(symbol) => write!(f, "\"{}\"", symbol),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 18] = [
+    "|stick|", "&&", "||", "<=", ">=", "==", "!=", "<", ">", "!", "(", ")", ",", "-", "+", "*", "/", "=",
+];
+
+pub(crate) fn tokenize(text: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let position = text.len() - rest.len();
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_digit() || c == '.' {
+            let end = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+            let number = rest[..end].parse().map_err(|_| syntax_error(position, &format!("invalid number \"{}\"", &rest[..end])))?;
+            tokens.push((position, Token::Number(number)));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            // C and D buttons can be written with their direction as a symbol, e.g. "C^" or "D<"
+            if matches!(&rest[..end], "C" | "c" | "D" | "d") && rest[end..].starts_with(['^', '<', '>']) {
+                end += 1;
+            }
+            tokens.push((position, Token::Name(rest[..end].to_string())));
+            rest = &rest[end..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push((position, Token::Symbol(symbol)));
+            rest = &rest[symbol.len()..];
+        } else {
+            return Err(syntax_error(position, &format!("unexpected \"{}\"", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+pub(crate) fn syntax_error(position: usize, message: &str) -> anyhow::Error {
+    M64Error { message: format!("At column {}: {}", position + 1, message) }.into()
+}
+
+/// Recursive descent parser over the tokens of an expression.
+pub(crate) struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    end: usize,
+}
+
+impl Parser {
+    pub(crate) fn new(text: &str) -> Result<Parser> {
+        Ok(Parser { tokens: tokenize(text)?, position: 0, end: text.len() })
+    }
+
+    pub(crate) fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    pub(crate) fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        token
+    }
+
+    pub(crate) fn column(&self) -> usize {
+        self.tokens.get(self.position).map_or(self.end, |(column, _)| *column)
+    }
+
+    pub(crate) fn error(&self, message: &str) -> anyhow::Error {
+        let found = match self.peek() {
+            Some(token) => format!("found {}", token),
+            None => "found end of input".to_string(),
+        };
+        syntax_error(self.column(), &format!("{}, {}", message, found))
+    }
+
+    pub(crate) fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    pub(crate) fn eat_name(&mut self, name: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Name(n)) if n.eq_ignore_ascii_case(name));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    pub(crate) fn expect(&mut self, symbol: &str) -> Result<()> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected \"{}\"", symbol)))
+        }
+    }
+
+    pub(crate) fn at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    pub(crate) fn predicate(&mut self) -> Result<Predicate> {
+        let mut predicate = self.and()?;
+        while self.eat("||") {
+            predicate = Predicate::Or(Box::new(predicate), Box::new(self.and()?));
+        }
+        Ok(predicate)
+    }
+
+    fn and(&mut self) -> Result<Predicate> {
+        let mut predicate = self.unary()?;
+        while self.eat("&&") {
+            predicate = Predicate::And(Box::new(predicate), Box::new(self.unary()?));
+        }
+        Ok(predicate)
+    }
+
+    fn unary(&mut self) -> Result<Predicate> {
+        if self.eat("!") {
+            return Ok(Predicate::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let predicate = self.predicate()?;
+            self.expect(")")?;
+            return Ok(predicate);
+        }
+        if self.eat("|stick|") {
+            return self.comparison(Value::Magnitude);
+        }
+        let column = self.column();
+        match self.next() {
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("held") => {
+                self.expect("(")?;
+                let predicate = self.predicate()?;
+                self.expect(",")?;
+                let frames = self.number()?;
+                self.expect(")")?;
+                Ok(Predicate::HeldFor(Box::new(predicate), frames as usize))
+            }
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("x") => self.comparison(Value::X),
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("y") => self.comparison(Value::Y),
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("angle") => self.comparison(Value::Angle),
+            Some(Token::Name(name)) => match name.parse::<Button>() {
+                Ok(button) => Ok(Predicate::Pressed(button)),
+                Err(_) => Err(syntax_error(column, &format!("unknown button or value \"{}\"", name))),
+            },
+            _ => {
+                self.position -= 1;
+                Err(self.error("expected a button or a comparison"))
+            }
+        }
+    }
+
+    fn comparison(&mut self, value: Value) -> Result<Predicate> {
+        let comparison = match self.next() {
+            Some(Token::Symbol("<")) => Comparison::Less,
+            Some(Token::Symbol("<=")) => Comparison::LessEqual,
+            Some(Token::Symbol(">")) => Comparison::Greater,
+            Some(Token::Symbol(">=")) => Comparison::GreaterEqual,
+            Some(Token::Symbol("==")) => Comparison::Equal,
+            Some(Token::Symbol("!=")) => Comparison::NotEqual,
+            _ => {
+                self.position -= 1;
+                return Err(self.error("expected a comparison"));
+            }
+        };
+        Ok(Predicate::Compare(value, comparison, self.number()?))
+    }
+
+    pub(crate) fn number(&mut self) -> Result<f64> {
+        let negative = self.eat("-");
+        match self.next() {
+            Some(Token::Number(number)) => Ok(if negative { -number } else { number }),
+            _ => {
+                self.position -= 1;
+                Err(self.error("expected a number"))
+            }
+        }
+    }
+}
+
+impl FromStr for Predicate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Predicate> {
+        let mut parser = Parser::new(s)?;
+        let predicate = parser.predicate()?;
+        if !parser.at_end() {
+            return Err(parser.error("expected \"&&\" or \"||\""));
+        }
+        Ok(predicate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn predicate(text: &str) -> Predicate {
+        text.parse().unwrap()
+    }
+
+    fn frames(text: &str, inputs: &[Input]) -> Vec<usize> {
+        predicate(text).evaluate(inputs).iter_ones().collect()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let a = Box::new(Predicate::Pressed(Button::A));
+        let b = Box::new(Predicate::Pressed(Button::B));
+        let z = Box::new(Predicate::Pressed(Button::Z));
+        assert_eq!(predicate("A || B && Z"), Predicate::Or(a.clone(), Box::new(Predicate::And(b.clone(), z.clone()))));
+        assert_eq!(predicate("(A || B) && Z"), Predicate::And(Box::new(Predicate::Or(a.clone(), b)), z));
+        assert_eq!(predicate("!!A"), Predicate::Not(Box::new(Predicate::Not(a))));
+        assert_eq!(predicate("x >= -5"), Predicate::Compare(Value::X, Comparison::GreaterEqual, -5.0));
+    }
+
+    #[test]
+    fn predicates_match_frames() {
+        let inputs = [
+            Input { x: 100, a_button: true, ..Input::new() },
+            Input { x: -3, y: 4, ..Input::new() },
+            Input { a_button: true, b_button: true, ..Input::new() },
+        ];
+        assert_eq!(frames("A && !B", &inputs), [0]);
+        assert_eq!(frames("|stick| == 5", &inputs), [1]);
+        assert_eq!(frames("x < 0 || B", &inputs), [1, 2]);
+        assert_eq!(frames("angle > 90 && angle < 180", &inputs), [1]);
+    }
+
+    #[test]
+    fn held_keeps_long_enough_runs() {
+        let pressed = [true, true, false, true, true, true, false, true];
+        let inputs: Vec<Input> = pressed.iter().map(|&z| Input { z_trig: z, ..Input::new() }).collect();
+        assert_eq!(frames("held(Z, 3)", &inputs), [3, 4, 5]);
+        assert_eq!(find(&inputs, &predicate("Z")), [0..2, 3..6, 7..8]);
+        assert_eq!(first(&inputs, &predicate("!Z")), Some(2));
+        assert_eq!(next(&inputs, &predicate("Z"), 3), Some(7));
+        assert_eq!(previous(&inputs, &predicate("Z"), 3), Some(0));
+        assert_eq!(next(&inputs, &predicate("Z"), 7), None);
+    }
+
+    #[test]
+    fn invalid_predicates_point_at_the_problem() {
+        for (text, error) in [
+            ("", "At column 1: expected a button or a comparison, found end of input"),
+            ("A &&", "At column 5: expected a button or a comparison, found end of input"),
+            ("A B", "At column 3: expected \"&&\" or \"||\", found \"B\""),
+            ("Q", "At column 1: unknown button or value \"Q\""),
+            ("x = 5", "At column 3: expected a comparison, found \"=\""),
+            ("(A", "At column 3: expected \")\", found end of input"),
+            ("held(A)", "At column 7: expected \",\", found \")\""),
+            ("A # B", "At column 3: unexpected \"#\""),
+            ("x > 1.2.3", "At column 5: invalid number \"1.2.3\""),
+        ] {
+            assert_eq!(text.parse::<Predicate>().unwrap_err().to_string(), error, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::api::m64_handling::{Button, Input};
+use crate::api::search::runs;
+use crate::api::stick;
+use bitvec::prelude::BitVec;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+// Width of the magnitude histogram bins; the largest magnitude is |(-128, -128)| ~ 181
+const MAGNITUDE_BIN: usize = 16;
+const MAGNITUDE_BINS: usize = 12;
+// Angle histogram bins, each 45 degrees wide and centred on its direction
+const DIRECTIONS: [&str; 8] = ["E", "NE", "N", "NW", "W", "SW", "S", "SE"];
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ButtonStats {
+    pub button: &'static str,
+    // Frames where the button goes from released to pressed
+    pub presses: usize,
+    pub held_frames: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Bin {
+    pub label: String,
+    pub frames: usize,
+}
+
+/// Statistics over a range of frames of one controller.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Stats {
+    pub start: usize,
+    pub frames: usize,
+    pub a_presses: usize,
+    pub buttons: Vec<ButtonStats>,
+    // Frames with no buttons pressed and the stick centred
+    pub idle_frames: usize,
+    pub longest_idle: Option<Range<usize>>,
+    pub magnitude_histogram: Vec<Bin>,
+    // Only frames with the stick off centre
+    pub angle_histogram: Vec<Bin>,
+}
+
+/// Computes the statistics of `range`, counting a button held since before the range as
+/// one press at its start. A reversed range is taken as empty.
+pub fn stats(inputs: &[Input], range: Range<usize>) -> Stats {
+    let end = range.end.min(inputs.len());
+    let range = range.start.min(end)..end;
+    let frames = &inputs[range.clone()];
+
+    let buttons: Vec<ButtonStats> = Button::ALL.into_iter()
+        .map(|button| {
+            let held = frames.iter().map(|input| input.pressed(button));
+            let previous = [false].into_iter().chain(held.clone());
+            ButtonStats {
+                button: button.label(),
+                presses: held.clone().zip(previous).filter(|&(now, before)| now && !before).count(),
+                held_frames: held.filter(|&pressed| pressed).count(),
+            }
+        })
+        .collect();
+    let a_presses = buttons[Button::ALL.iter().position(|&b| b == Button::A).unwrap()].presses;
+
+    let idle: BitVec = frames.iter().map(|input| *input == Input::new()).collect();
+    // Reversed so that the first of equally long stretches is reported
+    let longest_idle = runs(&idle).into_iter()
+        .rev()
+        .max_by_key(Range::len)
+        .map(|run| range.start + run.start..range.start + run.end);
+
+    let mut magnitudes = vec![0; MAGNITUDE_BINS];
+    let mut angles = vec![0; DIRECTIONS.len()];
+    for input in frames {
+        let magnitude = stick::magnitude(input.x, input.y);
+        magnitudes[(magnitude as usize / MAGNITUDE_BIN).min(MAGNITUDE_BINS - 1)] += 1;
+        if magnitude > 0.0 {
+            let direction = ((stick::angle(input.x, input.y) + 22.5) / 45.0) as usize % DIRECTIONS.len();
+            angles[direction] += 1;
+        }
+    }
+
+    Stats {
+        start: range.start,
+        frames: frames.len(),
+        a_presses,
+        buttons,
+        idle_frames: idle.count_ones(),
+        longest_idle,
+        magnitude_histogram: magnitudes.into_iter().enumerate()
+            .map(|(i, frames)| Bin { label: format!("{}-{}", i * MAGNITUDE_BIN, (i + 1) * MAGNITUDE_BIN - 1), frames })
+            .collect(),
+        angle_histogram: DIRECTIONS.iter().zip(angles)
+            .map(|(direction, frames)| Bin { label: direction.to_string(), frames })
+            .collect(),
+    }
+}
+
+impl Stats {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Frames:     {} (from frame {})", self.frames, self.start)?;
+        writeln!(f, "A presses:  {}", self.a_presses)?;
+        writeln!(f, "Idle:       {} frames", self.idle_frames)?;
+        match &self.longest_idle {
+            Some(range) => writeln!(f, "Longest idle: {} frames, {}-{}", range.len(), range.start, range.end - 1)?,
+            None => writeln!(f, "Longest idle: none")?,
+        }
+        writeln!(f)?;
+        writeln!(f, "Button  Presses   Held")?;
+        for button in &self.buttons {
+            writeln!(f, "{:<6} {:>8} {:>6}", button.button, button.presses, button.held_frames)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Magnitude  Frames")?;
+        for bin in &self.magnitude_histogram {
+            writeln!(f, "{:<9} {:>7}", bin.label, bin.frames)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Direction  Frames")?;
+        for bin in &self.angle_histogram {
+            writeln!(f, "{:<9} {:>7}", bin.label, bin.frames)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_buttons_count_as_one_press() {
+        let pressed = [true, true, false, true, false];
+        let inputs: Vec<Input> = pressed.iter().map(|&a| Input { a_button: a, ..Input::new() }).collect();
+        let stats = stats(&inputs, 1..5);
+        assert_eq!((stats.start, stats.frames, stats.a_presses), (1, 4, 2));
+        assert_eq!(stats.longest_idle, Some(2..3));
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_inputs() {
+        let inputs = vec![Input::new(); 10];
+        assert_eq!(stats(&inputs, 5..20).frames, 5);
+        assert_eq!(stats(&inputs, 20..30).frames, 0);
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 8..3;
+        assert_eq!(stats(&inputs, reversed).frames, 0);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::api::m64_handling::Input;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+/// The raw stick range is a signed byte, but the usable range is treated as
+/// symmetric so that mirrored values stay representable.
+pub const STICK_MAX: f64 = 127.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnapMode {
+    Free,
+    Cardinals,
+    Octants,
+    FixedMagnitude(f64),
+}
+
+impl SnapMode {
+    pub const MAGNITUDES: [f64; 3] = [32.0, 64.0, 127.0];
+
+    /// Cycles through the snap modes in the order the stick editor presents them.
+    pub fn next(self) -> SnapMode {
+        match self {
+            SnapMode::Free => SnapMode::Cardinals,
+            SnapMode::Cardinals => SnapMode::Octants,
+            SnapMode::Octants => SnapMode::FixedMagnitude(Self::MAGNITUDES[0]),
+            SnapMode::FixedMagnitude(m) => {
+                match Self::MAGNITUDES.iter().position(|&i| i == m) {
+                    Some(i) if i + 1 < Self::MAGNITUDES.len() => SnapMode::FixedMagnitude(Self::MAGNITUDES[i + 1]),
+                    _ => SnapMode::Free,
+                }
+            }
+        }
+    }
+}
+
+impl Display for SnapMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapMode::Free => write!(f, "Free"),
+            SnapMode::Cardinals => write!(f, "Cardinals"),
+            SnapMode::Octants => write!(f, "Octants"),
+            SnapMode::FixedMagnitude(m) => write!(f, "Magnitude {}", m),
+        }
+    }
+}
+
+pub fn magnitude(x: i8, y: i8) -> f64 {
+    (x as f64).hypot(y as f64)
+}
+
+/// Angle of the stick in degrees, counter-clockwise from the positive X axis, in `[0, 360)`.
+pub fn angle(x: i8, y: i8) -> f64 {
+    (y as f64).atan2(x as f64).to_degrees().rem_euclid(360.0)
+}
+
+/// Rounds a stick coordinate to the nearest raw value, saturating at the ends of the `i8` range.
+pub fn to_raw(value: f64) -> i8 {
+    value.round().clamp(i8::MIN as f64, i8::MAX as f64) as i8
+}
+
+/// Snaps a free stick position to the nearest position allowed by `mode`.
+pub fn snap(x: f64, y: f64, mode: SnapMode) -> (i8, i8) {
+    let magnitude = x.hypot(y);
+    let angle = y.atan2(x);
+    let (x, y) = match mode {
+        SnapMode::Free => (x, y),
+        SnapMode::Cardinals => snap_angle(magnitude, angle, PI / 2.0),
+        SnapMode::Octants => snap_angle(magnitude, angle, PI / 4.0),
+        SnapMode::FixedMagnitude(m) if magnitude > 0.0 => (angle.cos() * m, angle.sin() * m),
+        SnapMode::FixedMagnitude(_) => (0.0, 0.0),
+    };
+    (to_raw(x), to_raw(y))
+}
+
+fn snap_angle(magnitude: f64, angle: f64, step: f64) -> (f64, f64) {
+    let angle = (angle / step).round() * step;
+    (angle.cos() * magnitude, angle.sin() * magnitude)
+}
+
+/// Stick position given as a magnitude and an angle in degrees, see [`angle`].
+pub fn from_polar(magnitude: f64, angle: f64) -> (i8, i8) {
+    let angle = angle.to_radians();
+    (to_raw(angle.cos() * magnitude), to_raw(angle.sin() * magnitude))
+}
+
+pub fn to_polar(x: i8, y: i8) -> (f64, f64) {
+    (magnitude(x, y), angle(x, y))
+}
+
+/// Negates a raw value, mapping -128 to 127 instead of overflowing.
+pub fn mirror(value: i8) -> i8 {
+    value.saturating_neg()
+}
+
+/// Outer limit of the stick positions, see [`clamp_to_gate`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gate {
+    Circle(f64),
+    // Regular octagon with its corners on the cardinals and diagonals, given by the magnitude of the corners
+    Octagon(f64),
+}
+
+impl Gate {
+    /// Largest magnitude allowed in the direction of `angle`, in radians.
+    pub fn limit(&self, angle: f64) -> f64 {
+        match *self {
+            Gate::Circle(radius) => radius,
+            Gate::Octagon(radius) => {
+                // Distance to the edge between the two corners surrounding the angle
+                let step = PI / 4.0;
+                let offset = angle.rem_euclid(step) - step / 2.0;
+                radius * (step / 2.0).cos() / offset.cos()
+            }
+        }
+    }
+}
+
+// Transforms over a range of frames, computed in floating point and rounded back to raw values.
+
+pub fn transform(inputs: &mut [Input], f: impl Fn(f64, f64) -> (f64, f64)) {
+    for input in inputs {
+        let (x, y) = f(input.x as f64, input.y as f64);
+        input.x = to_raw(x);
+        input.y = to_raw(y);
+    }
+}
+
+pub fn mirror_x(inputs: &mut [Input]) {
+    inputs.iter_mut().for_each(|input| input.x = mirror(input.x));
+}
+
+pub fn mirror_y(inputs: &mut [Input]) {
+    inputs.iter_mut().for_each(|input| input.y = mirror(input.y));
+}
+
+/// Rotates counter-clockwise by `degrees`.
+pub fn rotate(inputs: &mut [Input], degrees: f64) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    transform(inputs, |x, y| (x * cos - y * sin, x * sin + y * cos));
+}
+
+pub fn scale(inputs: &mut [Input], factor: f64) {
+    transform(inputs, |x, y| (x * factor, y * factor));
+}
+
+/// Pulls positions outside `gate` back onto its edge, keeping their angle.
+pub fn clamp_to_gate(inputs: &mut [Input], gate: Gate) {
+    transform(inputs, |x, y| {
+        let magnitude = x.hypot(y);
+        let limit = gate.limit(y.atan2(x));
+        if magnitude > limit {
+            (x * limit / magnitude, y * limit / magnitude)
+        } else {
+            (x, y)
+        }
+    });
+}
+
+/// Zeroes positions whose magnitude is below `deadzone`.
+pub fn remove_deadzone(inputs: &mut [Input], deadzone: f64) {
+    for input in inputs {
+        if magnitude(input.x, input.y) < deadzone {
+            input.x = 0;
+            input.y = 0;
+        }
+    }
+}
+
+/// Rescales magnitudes from `[0, STICK_MAX]` to `[deadzone, STICK_MAX]`, so that every
+/// non-neutral position lands outside a game's deadzone.
+pub fn remap_deadzone(inputs: &mut [Input], deadzone: f64) {
+    transform(inputs, |x, y| {
+        let magnitude = x.hypot(y);
+        if magnitude == 0.0 {
+            return (0.0, 0.0);
+        }
+        let remapped = deadzone + magnitude * (STICK_MAX - deadzone) / STICK_MAX;
+        (x * remapped / magnitude, y * remapped / magnitude)
+    });
+}
+
+/// A game's processing of the raw stick position into the value it acts on.
+pub trait StickModel {
+    fn name(&self) -> &str;
+
+    fn effective(&self, x: i8, y: i8) -> (f64, f64);
+}
+
+/// The raw values, for games that use them as they are.
+pub struct RawStick;
+
+impl StickModel for RawStick {
+    fn name(&self) -> &str {
+        "Raw"
+    }
+
+    fn effective(&self, x: i8, y: i8) -> (f64, f64) {
+        (x as f64, y as f64)
+    }
+}
+
+pub const SM64_DEADZONE: f64 = 8.0;
+pub const SM64_MAX_MAGNITUDE: f64 = 64.0;
+
+/// Super Mario 64's adjust_analog_stick: each axis loses a deadzone of 8, leaving values from
+/// 2 up, and the magnitude is capped at 64.
+pub struct Sm64Stick;
+
+impl StickModel for Sm64Stick {
+    fn name(&self) -> &str {
+        "SM64"
+    }
+
+    fn effective(&self, x: i8, y: i8) -> (f64, f64) {
+        let axis = |value: i8| {
+            let value = value as f64;
+            if value <= -SM64_DEADZONE {
+                value + SM64_DEADZONE - 2.0
+            } else if value >= SM64_DEADZONE {
+                value - SM64_DEADZONE + 2.0
+            } else {
+                0.0
+            }
+        };
+        let (x, y) = (axis(x), axis(y));
+        let magnitude = x.hypot(y);
+        if magnitude > SM64_MAX_MAGNITUDE {
+            (x * SM64_MAX_MAGNITUDE / magnitude, y * SM64_MAX_MAGNITUDE / magnitude)
+        } else {
+            (x, y)
+        }
+    }
+}
+
+/// The built-in models, in the order the editor cycles through them.
+pub fn models() -> Vec<Arc<dyn StickModel>> {
+    vec![Arc::new(RawStick), Arc::new(Sm64Stick)]
+}
+
+/// Replaces each position with the smallest raw position that `model` treats the same.
+///
+/// Smallest means nearest the centre, then with the smaller sum of absolute values.
+pub fn normalise(inputs: &mut [Input], model: &dyn StickModel) {
+    let mut positions: Vec<(i8, i8)> = (i8::MIN..=i8::MAX)
+        .flat_map(|x| (i8::MIN..=i8::MAX).map(move |y| (x, y)))
+        .collect();
+    positions.sort_by_key(|&(x, y)| {
+        let (x, y) = (x as i32, y as i32);
+        (x * x + y * y, x.abs() + y.abs(), x, y)
+    });
+    // Effective values are compared bit for bit, so that only identical input is merged
+    let mut smallest = HashMap::new();
+    for (x, y) in positions {
+        let (ex, ey) = model.effective(x, y);
+        smallest.entry((ex.to_bits(), ey.to_bits())).or_insert((x, y));
+    }
+    for input in inputs {
+        let (ex, ey) = model.effective(input.x, input.y);
+        (input.x, input.y) = smallest[&(ex.to_bits(), ey.to_bits())];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(positions: &[(i8, i8)]) -> Vec<Input> {
+        positions.iter().map(|&(x, y)| Input { x, y, ..Input::new() }).collect()
+    }
+
+    fn positions(inputs: &[Input]) -> Vec<(i8, i8)> {
+        inputs.iter().map(|input| (input.x, input.y)).collect()
+    }
+
+    #[test]
+    fn mirroring_saturates() {
+        let mut moved = inputs(&[(-128, 127), (5, -5)]);
+        mirror_x(&mut moved);
+        assert_eq!(positions(&moved), vec![(127, 127), (-5, -5)]);
+        mirror_y(&mut moved);
+        assert_eq!(positions(&moved), vec![(127, -127), (-5, 5)]);
+    }
+
+    #[test]
+    fn rotation_is_counter_clockwise() {
+        let mut moved = inputs(&[(100, 0), (0, 50)]);
+        rotate(&mut moved, 90.0);
+        assert_eq!(positions(&moved), vec![(0, 100), (-50, 0)]);
+        rotate(&mut moved, -90.0);
+        assert_eq!(positions(&moved), vec![(100, 0), (0, 50)]);
+    }
+
+    #[test]
+    fn scaling_saturates_at_the_raw_range() {
+        let mut moved = inputs(&[(100, -100), (10, 0)]);
+        scale(&mut moved, 2.0);
+        assert_eq!(positions(&moved), vec![(127, -128), (20, 0)]);
+    }
+
+    #[test]
+    fn gates_limit_the_magnitude() {
+        let mut moved = inputs(&[(127, 0), (30, 40), (90, 90)]);
+        clamp_to_gate(&mut moved, Gate::Circle(50.0));
+        assert_eq!(positions(&moved), vec![(50, 0), (30, 40), (35, 35)]);
+
+        // The corners of the octagon are as far out as a circle, its edges are closer in
+        let octagon = Gate::Octagon(100.0);
+        assert!((octagon.limit(0.0) - 100.0).abs() < 1e-9);
+        assert!((octagon.limit(PI / 4.0) - 100.0).abs() < 1e-9);
+        assert!(octagon.limit(PI / 8.0) < 100.0);
+    }
+
+    #[test]
+    fn deadzone_remapping_keeps_the_ends() {
+        let mut moved = inputs(&[(0, 0), (1, 0), (127, 0), (0, -127)]);
+        remap_deadzone(&mut moved, 8.0);
+        assert_eq!(positions(&moved), vec![(0, 0), (9, 0), (127, 0), (0, -127)]);
+
+        let mut removed = inputs(&[(5, 5), (6, 6), (-8, 0)]);
+        remove_deadzone(&mut removed, 8.0);
+        assert_eq!(positions(&removed), vec![(0, 0), (6, 6), (-8, 0)]);
+    }
+    #[test]
+    fn sm64_deadzone_starts_at_8() {
+        let model = Sm64Stick;
+        assert_eq!(model.effective(7, -7), (0.0, 0.0));
+        assert_eq!(model.effective(8, -8), (2.0, -2.0));
+        assert_eq!(model.effective(20, 0), (14.0, 0.0));
+    }
+
+    #[test]
+    fn sm64_magnitude_is_capped_at_64() {
+        let model = Sm64Stick;
+        assert_eq!(model.effective(70, 0), (64.0, 0.0));
+        assert_eq!(model.effective(127, 0), (64.0, 0.0));
+        assert_eq!(model.effective(-128, 0), (-64.0, 0.0));
+        let (x, y) = model.effective(127, 127);
+        assert!((x.hypot(y) - 64.0).abs() < 1e-9 && (x - y).abs() < 1e-9);
+    }
+
+    #[test]
+    fn normalising_picks_the_smallest_equivalent_position() {
+        let mut moved = inputs(&[(5, -3), (127, 0), (-128, 0), (20, 0)]);
+        normalise(&mut moved, &Sm64Stick);
+        assert_eq!(positions(&moved), vec![(0, 0), (70, 0), (-70, 0), (20, 0)]);
+        let mut raw = inputs(&[(5, -3), (-128, 127)]);
+        normalise(&mut raw, &RawStick);
+        assert_eq!(positions(&raw), vec![(5, -3), (-128, 127)]);
+    }
+}
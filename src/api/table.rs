@@ -0,0 +1,334 @@
+use crate::api::m64_handling::{Button, Input, M64Error, M64File};
+use crate::api::movie_text::{header_lines, set_field};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt::Write;
+
+// Inputs as a table for spreadsheets and analysis tools. CSV has one row per frame with a
+// column per stick axis and button of each controller, e.g. "p1_x" or "p2_c_up", and the
+// header fields as "# field value" comment lines before it. Buttons are 1 when pressed.
+// JSON has the header fields and a list of frames per controller.
+
+/// Column names of the buttons, in the order they are written.
+const BUTTON_COLUMNS: [(Button, &str); 14] = [
+    (Button::A, "a"), (Button::B, "b"), (Button::Z, "z"), (Button::Start, "start"),
+    (Button::L, "l"), (Button::R, "r"),
+    (Button::CUp, "c_up"), (Button::CDown, "c_down"), (Button::CLeft, "c_left"), (Button::CRight, "c_right"),
+    (Button::DUp, "d_up"), (Button::DDown, "d_down"), (Button::DLeft, "d_left"), (Button::DRight, "d_right"),
+];
+
+// Header fields written as JSON numbers and strings, the rest stay in their text form
+const NUMBER_FIELDS: [&str; 8] = [
+    "version", "uid", "vi_count", "rerecord_count", "vi_per_second", "controller_count", "num_samples", "movie_start_type",
+];
+const STRING_FIELDS: [&str; 7] = [
+    "internal_name", "video_plugin", "sound_plugin", "input_plugin", "rsp_plugin", "author", "movie_desc",
+];
+
+fn error(message: String) -> anyhow::Error {
+    M64Error { message }.into()
+}
+
+/// Controllers to write: the active ones, and any other that has inputs.
+fn exported_controllers(movie: &M64File) -> Vec<usize> {
+    let active = M64File::active_controllers(movie.controller_flags).unwrap_or_default();
+    (0..4).filter(|i| active.contains(i) || !movie.inputs[*i].is_empty()).collect()
+}
+
+fn button_name(button: Button) -> &'static str {
+    BUTTON_COLUMNS.iter().find(|(b, _)| *b == button).unwrap().1
+}
+
+fn parse_button(name: &str) -> Result<Button, String> {
+    BUTTON_COLUMNS.iter()
+        .find(|(_, n)| n.eq_ignore_ascii_case(name))
+        .map(|(button, _)| *button)
+        .ok_or_else(|| format!("unknown button \"{}\"", name))
+}
+
+pub fn to_csv(movie: &M64File) -> String {
+    let mut text = String::new();
+    for (field, value) in header_lines(movie) {
+        writeln!(text, "# {} {}", field, value).unwrap();
+    }
+    let controllers = exported_controllers(movie);
+    let mut columns = vec!["frame".to_string()];
+    for controller in &controllers {
+        columns.push(format!("p{}_x", controller + 1));
+        columns.push(format!("p{}_y", controller + 1));
+        columns.extend(BUTTON_COLUMNS.iter().map(|(_, name)| format!("p{}_{}", controller + 1, name)));
+    }
+    writeln!(text, "{}", columns.join(",")).unwrap();
+
+    let frames = controllers.iter().map(|&i| movie.inputs[i].len()).max().unwrap_or(0);
+    for frame in 0..frames {
+        let mut row = vec![frame.to_string()];
+        for &controller in &controllers {
+            // Controllers with fewer frames leave their cells empty
+            let Some(input) = movie.inputs[controller].get(frame) else {
+                row.extend(std::iter::repeat_n(String::new(), 2 + BUTTON_COLUMNS.len()));
+                continue;
+            };
+            row.push(input.x.to_string());
+            row.push(input.y.to_string());
+            row.extend(BUTTON_COLUMNS.iter().map(|(button, _)| (input.pressed(*button) as u8).to_string()));
+        }
+        writeln!(text, "{}", row.join(",")).unwrap();
+    }
+    text
+}
+
+fn csv_row(line: &str) -> Vec<String> {
+    let mut cells = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut cell = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' if chars.next_if_eq(&'"').is_some() => cell.push('"'),
+                    '"' => break,
+                    c => cell.push(c),
+                }
+            }
+        }
+        // Anything after a closing quote, or an unquoted cell, is read as is
+        while let Some(c) = chars.next_if(|&c| c != ',') {
+            cell.push(c);
+        }
+        cells.push(cell);
+        if chars.next().is_none() {
+            return cells;
+        }
+    }
+}
+
+// Which cell of a row holds each part of a controller's input
+#[derive(Default)]
+struct ControllerColumns {
+    x: Option<usize>,
+    y: Option<usize>,
+    buttons: Vec<(Button, usize)>,
+}
+
+fn parse_cell<T: std::str::FromStr + Default>(row: &[String], column: Option<usize>) -> Result<T, String> {
+    let Some(cell) = column.and_then(|column| row.get(column)).map(|cell| cell.trim()) else {
+        return Ok(T::default());
+    };
+    if cell.is_empty() {
+        return Ok(T::default());
+    }
+    cell.parse().map_err(|_| format!("invalid value \"{}\"", cell))
+}
+
+fn parse_pressed(cell: &str) -> Result<bool, String> {
+    match cell.trim().to_ascii_lowercase().as_str() {
+        "" | "0" | "false" => Ok(false),
+        "1" | "true" => Ok(true),
+        other => Err(format!("invalid button value \"{}\"", other)),
+    }
+}
+
+/// Reads a movie written by [`to_csv`]. Columns can be reordered or left out, and missing
+/// header fields keep their defaults.
+pub fn from_csv(text: &str) -> Result<M64File> {
+    let mut movie = M64File::new();
+    let mut columns: Option<[Option<ControllerColumns>; 4]> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line_error = |message: String| error(format!("Line {}: {}", i + 1, message));
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row = csv_row(line);
+        // Spreadsheets may quote comment lines or pad them with empty cells
+        let comment = if line.trim_start().starts_with('#') { line.to_string() } else { row.join(",") };
+        if let Some(comment) = comment.trim_start().strip_prefix('#') {
+            let comment = comment.trim_end_matches(',').trim();
+            if let Some((field, value)) = comment.split_once(' ') {
+                set_field(&mut movie, field, value.trim()).map_err(line_error)?;
+            }
+            continue;
+        }
+        let Some(columns) = &columns else {
+            columns = Some(parse_columns(&row).map_err(line_error)?);
+            continue;
+        };
+        for (controller, controller_columns) in columns.iter().enumerate() {
+            let Some(controller_columns) = controller_columns else { continue };
+            let mut cells = controller_columns.buttons.iter().map(|(_, column)| *column)
+                .chain(controller_columns.x)
+                .chain(controller_columns.y);
+            // A row with all of a controller's cells empty is past the end of its inputs
+            if cells.all(|column| row.get(column).is_none_or(|cell| cell.trim().is_empty())) {
+                continue;
+            }
+            let mut input = Input::new();
+            input.x = parse_cell(&row, controller_columns.x).map_err(line_error)?;
+            input.y = parse_cell(&row, controller_columns.y).map_err(line_error)?;
+            for (button, column) in &controller_columns.buttons {
+                input.set(*button, parse_pressed(row.get(*column).map_or("", |cell| cell)).map_err(line_error)?);
+            }
+            movie.inputs[controller].push(input);
+        }
+    }
+    if columns.is_none() {
+        return Err(error("Missing the row of column names".to_string()));
+    }
+    finish(movie)
+}
+
+fn parse_columns(row: &[String]) -> Result<[Option<ControllerColumns>; 4], String> {
+    let mut columns: [Option<ControllerColumns>; 4] = Default::default();
+    for (column, name) in row.iter().enumerate() {
+        let name = name.trim().to_ascii_lowercase();
+        if name == "frame" {
+            continue;
+        }
+        let (port, part) = name.strip_prefix('p')
+            .and_then(|rest| rest.split_once('_'))
+            .ok_or(format!("unknown column \"{}\"", name))?;
+        let controller = match port.parse::<usize>() {
+            Ok(port @ 1..=4) => port - 1,
+            _ => return Err(format!("invalid controller in column \"{}\"", name)),
+        };
+        let controller_columns = columns[controller].get_or_insert_default();
+        match part {
+            "x" => controller_columns.x = Some(column),
+            "y" => controller_columns.y = Some(column),
+            part => {
+                controller_columns.buttons.push((parse_button(part)?, column));
+            }
+        }
+    }
+    Ok(columns)
+}
+
+// Marks the imported controllers as connected and updates the sample count
+fn finish(mut movie: M64File) -> Result<M64File> {
+    for (controller, inputs) in movie.inputs.iter().enumerate() {
+        if !inputs.is_empty() {
+            movie.controller_flags |= 1 << controller;
+        }
+    }
+    let active = M64File::active_controllers(movie.controller_flags)?;
+    movie.controller_count = active.len() as u8;
+    movie.num_samples = active.iter().map(|&i| movie.inputs[i].len()).max().unwrap_or(0) as u32;
+    Ok(movie)
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonFrame {
+    x: i8,
+    y: i8,
+    #[serde(default)]
+    buttons: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonController {
+    port: usize,
+    inputs: Vec<JsonFrame>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonMovie {
+    #[serde(default)]
+    header: Map<String, Value>,
+    controllers: Vec<JsonController>,
+}
+
+pub fn to_json(movie: &M64File) -> String {
+    let header = header_lines(movie).into_iter()
+        .map(|(field, value)| {
+            let value = if NUMBER_FIELDS.contains(&field) {
+                // Every number field fits, including the signed uid
+                Value::from(value.parse::<i64>().unwrap())
+            } else if STRING_FIELDS.contains(&field) {
+                // Keeps the escapes of the text form for bytes that aren't printable
+                Value::from(&value[1..value.len() - 1])
+            } else {
+                Value::from(value)
+            };
+            (field.to_string(), value)
+        })
+        .collect();
+    let controllers = exported_controllers(movie).into_iter()
+        .map(|controller| JsonController {
+            port: controller + 1,
+            inputs: movie.inputs[controller].iter()
+                .map(|input| JsonFrame {
+                    x: input.x,
+                    y: input.y,
+                    buttons: BUTTON_COLUMNS.iter()
+                        .filter(|(button, _)| input.pressed(*button))
+                        .map(|(button, _)| button_name(*button).to_string())
+                        .collect(),
+                })
+                .collect(),
+        })
+        .collect();
+    serde_json::to_string_pretty(&JsonMovie { header, controllers }).unwrap()
+}
+
+/// Reads a movie written by [`to_json`]. Missing header fields keep their defaults.
+pub fn from_json(text: &str) -> Result<M64File> {
+    let json: JsonMovie = serde_json::from_str(text)?;
+    let mut movie = M64File::new();
+    for (field, value) in &json.header {
+        let value = match value {
+            Value::String(text) if STRING_FIELDS.contains(&field.as_str()) => format!("\"{}\"", text),
+            Value::String(text) => text.clone(),
+            Value::Number(number) => number.to_string(),
+            _ => return Err(error(format!("Invalid value for {}", field))),
+        };
+        set_field(&mut movie, field, &value).map_err(|message| error(format!("{}: {}", field, message)))?;
+    }
+    for controller in json.controllers {
+        if !(1..=4).contains(&controller.port) {
+            return Err(error(format!("Invalid controller {}", controller.port)));
+        }
+        let inputs = &mut movie.inputs[controller.port - 1];
+        for frame in controller.inputs {
+            let mut input = Input::new();
+            input.x = frame.x;
+            input.y = frame.y;
+            for button in &frame.buttons {
+                input.set(parse_button(button).map_err(error)?, true);
+            }
+            inputs.push(input);
+        }
+    }
+    finish(movie)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::m64_handling::{string_to_ascii, test_movie};
+
+    fn edited_movie() -> M64File {
+        let mut movie = test_movie(20, 0b1010 | 0x20);
+        movie.uid = -123456;
+        movie.rerecord_count = 99;
+        movie.author = string_to_ascii("a, \"quoted\" author").unwrap();
+        movie.inputs[1][3].start = true;
+        movie.inputs[3][19].l_trig = true;
+        movie.inputs[3][0].y = -128;
+        movie
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let movie = edited_movie();
+        let imported = from_csv(&to_csv(&movie)).unwrap();
+        assert_eq!(imported.to_bytes().unwrap(), movie.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let movie = edited_movie();
+        let imported = from_json(&to_json(&movie)).unwrap();
+        assert_eq!(imported.to_bytes().unwrap(), movie.to_bytes().unwrap());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::api::m64_handling::{M64Error, M64File};
+use anyhow::Result;
+
+// Country codes of PAL releases, which run at 50 VIs per second instead of 60
+const PAL_COUNTRY_CODES: [u8; 8] = [b'D', b'F', b'I', b'P', b'S', b'U', b'X', b'Y'];
+
+/// Conversions between input frames, VIs and seconds for one movie.
+///
+/// Games poll the controller on some VIs only, so the VIs per frame are averaged over the
+/// movie. Times are written `h:mm:ss.ff` with `ff` in hundredths of a second, and leading
+/// fields can be left out, e.g. `1:23.40` or `83.4`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    pub vi_per_second: f64,
+    pub vi_per_frame: f64,
+}
+
+impl Timing {
+    pub fn of(movie: &M64File) -> Timing {
+        let vi_per_second = match movie.vi_per_second {
+            0 if PAL_COUNTRY_CODES.contains(&(movie.country_code as u8)) => 50.0,
+            0 => 60.0,
+            vi_per_second => vi_per_second as f64,
+        };
+        let vi_per_frame = match (movie.vi_count, movie.num_samples) {
+            (0, _) | (_, 0) => 1.0,
+            (vi_count, num_samples) => vi_count as f64 / num_samples as f64,
+        };
+        Timing { vi_per_second, vi_per_frame }
+    }
+
+    pub fn frame_to_vi(&self, frame: usize) -> u64 {
+        (frame as f64 * self.vi_per_frame).round() as u64
+    }
+
+    pub fn vi_to_frame(&self, vi: u64) -> usize {
+        (vi as f64 / self.vi_per_frame).round() as usize
+    }
+
+    pub fn frame_to_seconds(&self, frame: usize) -> f64 {
+        frame as f64 * self.vi_per_frame / self.vi_per_second
+    }
+
+    pub fn seconds_to_frame(&self, seconds: f64) -> usize {
+        (seconds * self.vi_per_second / self.vi_per_frame).round() as usize
+    }
+
+    /// Parses a frame given as a sum of terms, e.g. `5000+120` or `1:23.40-30`. A term is a
+    /// frame number, a time, or a VI count with a `vi` suffix.
+    pub fn parse_frame(&self, text: &str) -> Result<usize> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(M64Error { message: "Expected a frame or a time".to_string() }.into());
+        }
+        let mut total = 0_i64;
+        let mut rest = text;
+        let mut sign = 1;
+        loop {
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            total += sign * self.parse_term(rest[..end].trim())? as i64;
+            if end == rest.len() {
+                break;
+            }
+            sign = if rest[end..].starts_with('-') { -1 } else { 1 };
+            rest = &rest[end + 1..];
+        }
+        usize::try_from(total).map_err(|_| M64Error { message: format!("\"{}\" is before the first frame", text) }.into())
+    }
+
+    fn parse_term(&self, term: &str) -> Result<usize> {
+        if let Some(vi) = term.strip_suffix("vi").or_else(|| term.strip_suffix("VI")) {
+            let vi = vi.trim().parse::<u64>().map_err(|_| invalid(term))?;
+            Ok(self.vi_to_frame(vi))
+        } else if term.contains([':', '.']) {
+            Ok(self.seconds_to_frame(parse_time(term)?))
+        } else {
+            term.parse::<usize>().map_err(|_| invalid(term))
+        }
+    }
+}
+
+fn invalid(term: &str) -> anyhow::Error {
+    M64Error { message: format!("\"{}\" is not a frame, time or VI count", term) }.into()
+}
+
+/// Seconds in a `h:mm:ss.ff` time, where the hours and minutes are optional.
+pub fn parse_time(text: &str) -> Result<f64> {
+    let mut seconds = 0.0;
+    let fields: Vec<&str> = text.trim().split(':').collect();
+    if fields.len() > 3 {
+        return Err(invalid(text));
+    }
+    for (i, field) in fields.iter().enumerate() {
+        let last = i == fields.len() - 1;
+        let value = if last { field.parse::<f64>().ok() } else { field.parse::<u32>().ok().map(f64::from) }
+            .ok_or_else(|| invalid(text))?;
+        if value < 0.0 || (i > 0 && value >= 60.0) {
+            return Err(invalid(text));
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Ok(seconds)
+}
+
+/// Formats seconds as `h:mm:ss.ff`.
+pub fn format_time(seconds: f64) -> String {
+    let hundredths = (seconds * 100.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        hundredths / 360_000,
+        hundredths / 6000 % 60,
+        hundredths / 100 % 60,
+        hundredths % 100
+    )
+}
+
+/// Length of the movie by its VI count.
+pub fn duration(movie: &M64File) -> f64 {
+    movie.vi_count as f64 / Timing::of(movie).vi_per_second
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::m64_handling::test_movie;
+
+    fn timing(vi_per_second: f64) -> Timing {
+        Timing { vi_per_second, vi_per_frame: 2.0 }
+    }
+
+    #[test]
+    fn frames_and_times_round_trip() {
+        for vi_per_second in [30.0, 25.0] {
+            let timing = timing(vi_per_second);
+            for frame in [0, 1, 14, 15, 1799, 54_000] {
+                let time = format_time(timing.frame_to_seconds(frame));
+                assert_eq!(timing.parse_frame(&time).unwrap(), frame, "{} at {} VI/s", time, vi_per_second);
+                assert_eq!(timing.vi_to_frame(timing.frame_to_vi(frame)), frame);
+            }
+        }
+        assert_eq!(format_time(timing(30.0).frame_to_seconds(900)), "0:01:00.00");
+        assert_eq!(format_time(timing(25.0).frame_to_seconds(900)), "0:01:12.00");
+    }
+
+    #[test]
+    fn frames_are_sums_of_terms() {
+        let timing = timing(60.0);
+        assert_eq!(timing.parse_frame("5000+120").unwrap(), 5120);
+        assert_eq!(timing.parse_frame(" 1:00 - 30 ").unwrap(), 1770);
+        assert_eq!(timing.parse_frame("120vi").unwrap(), 60);
+        assert_eq!(timing.parse_frame("1.5").unwrap(), 45);
+    }
+
+    #[test]
+    fn invalid_frames_are_rejected() {
+        let timing = timing(60.0);
+        for text in ["", "abc", "1:60", "1:2:3:4", "-5", "10-20", "1:-5", "xvi", "5+"] {
+            assert!(timing.parse_frame(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn timing_follows_the_header() {
+        let mut movie = test_movie(100, 0b0001);
+        assert_eq!(Timing::of(&movie), Timing { vi_per_second: 60.0, vi_per_frame: 2.0 });
+        movie.vi_per_second = 0;
+        movie.country_code = b'P' as u16;
+        movie.num_samples = 0;
+        assert_eq!(Timing::of(&movie), Timing { vi_per_second: 50.0, vi_per_frame: 1.0 });
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use m64_editor::api::file_handling::{read_file, save_file};
+use m64_editor::api::format::Format;
+use m64_editor::api::m64_handling::M64File;
+use m64_editor::api::merge::merge;
+use m64_editor::api::movie_text;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Git helpers for Mupen64 movies.
+///
+/// Set them up for .m64 files with `*.m64 diff=m64 merge=m64` in .gitattributes, and in the
+/// git config:
+///
+///     [diff "m64"]
+///         textconv = m64-git textconv
+///     [merge "m64"]
+///         name = frame-aware m64 merge
+///         driver = m64-git merge %O %A %B
+#[derive(Parser)]
+#[command(name = "m64-git", version, verbatim_doc_comment)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints a movie as its header fields and one line per frame, for git diff
+    Textconv { movie: PathBuf },
+    /// Merges the changes in ours and theirs since base into ours, for git merge.
+    /// Conflicts leave ours in the text form with conflict markers, and exit with 1
+    Merge { base: PathBuf, ours: PathBuf, theirs: PathBuf },
+}
+
+/// Reads a movie in any format, including the text form a conflicted merge leaves behind,
+/// along with the format it was in.
+fn open(path: &Path) -> Result<(M64File, Format)> {
+    let data = read_file(path)?;
+    let format = Format::of(path, &data);
+    let conversion = format.decode(&data)?;
+    for warning in conversion.warnings {
+        eprintln!("{}: {}", path.display(), warning);
+    }
+    Ok((conversion.movie, format))
+}
+
+fn main() -> Result<ExitCode> {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Textconv { movie } => {
+            print!("{}", movie_text::encode(&open(&movie)?.0));
+        }
+        Command::Merge { base, ours, theirs } => {
+            let (our_movie, format) = open(&ours)?;
+            let merged = merge(&open(&base)?.0, &our_movie, &open(&theirs)?.0)?;
+            if merged.conflicts.is_empty() && merged.header_conflicts.is_empty() {
+                // Kept in the format ours was in, which is text if it was a resolved conflict
+                save_file(&ours, &format.encode(&merged.to_movie())?.movie)?;
+            } else {
+                save_file(&ours, &merged.to_text().into_bytes())?;
+                eprintln!(
+                    "{}: {} input conflicts and {} header conflicts, written as text",
+                    ours.display(),
+                    merged.conflicts.len(),
+                    merged.header_conflicts.len()
+                );
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
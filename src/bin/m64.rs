@@ -0,0 +1,429 @@
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+use m64_editor::api::batch::{self, find_movies, Job, Outcome, Status};
+use m64_editor::api::diff::diff;
+use m64_editor::api::file_handling::{read_file, save_file, uncompressed_path};
+use m64_editor::api::format::Format;
+use m64_editor::api::m64_handling::{Input, M64File};
+use m64_editor::api::scripting::run_script;
+use m64_editor::api::stats::stats;
+use m64_editor::api::timecode::{self, format_time, Timing};
+use serde_json::json;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Command-line tools for Mupen64 movies.
+///
+/// Movies can be given as `-` to read standard input, and are written to standard output
+/// unless an output is given. Frames can be given as numbers, times or sums, e.g. `1:23.40`
+/// or `5000+120`.
+#[derive(Parser)]
+#[command(name = "m64", version)]
+struct Cli {
+    /// Prints reports as JSON
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct Output {
+    /// Where to write the movie, `-` for standard output
+    #[arg(short, long, default_value = "-")]
+    output: PathBuf,
+    /// Format to write: m64, bk2, text, csv or json. By default the output's extension, or m64
+    #[arg(short, long)]
+    format: Option<Format>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Shows the header fields, length and controllers of a movie
+    Info { movie: PathBuf },
+    /// Checks movies for problems with their header and inputs
+    Validate {
+        #[arg(required = true)]
+        movies: Vec<PathBuf>,
+    },
+    /// Keeps only the frames from START up to, not including, END
+    Trim {
+        movie: PathBuf,
+        #[arg(long)]
+        start: Option<String>,
+        #[arg(long)]
+        end: Option<String>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Inserts neutral frames, or the frames of another movie, before frame AT
+    Insert {
+        movie: PathBuf,
+        at: String,
+        /// Number of neutral frames to insert
+        #[arg(long, default_value_t = 1, conflicts_with = "from")]
+        frames: usize,
+        /// Movie whose frames are inserted
+        #[arg(long)]
+        from: Option<PathBuf>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Removes the frames from START up to, not including, END
+    Remove {
+        movie: PathBuf,
+        start: String,
+        end: String,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Replaces the frames from START up to, not including, END with the frames of SOURCE
+    Splice {
+        movie: PathBuf,
+        start: String,
+        end: String,
+        source: PathBuf,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Joins movies one after the other, keeping the header of the first and adding up their
+    /// rerecord and VI counts. Controllers missing from a movie get neutral inputs
+    Concat {
+        #[arg(required = true, num_args = 2..)]
+        movies: Vec<PathBuf>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Cuts a movie at each of the given frames, writing the parts as NAME.1.m64, NAME.2.m64, ...
+    Split {
+        movie: PathBuf,
+        #[arg(required = true)]
+        frames: Vec<String>,
+        /// Directory to write the parts to, by default the movie's directory
+        #[arg(short, long)]
+        directory: Option<PathBuf>,
+        /// Format to write the parts in, by default m64
+        #[arg(short, long)]
+        format: Option<Format>,
+    },
+    /// Converts a movie to another format
+    Convert {
+        movie: PathBuf,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Lists the header fields and frames that differ between two movies
+    Diff { old: PathBuf, new: PathBuf },
+    /// Shows input statistics of one controller
+    Stats {
+        movie: PathBuf,
+        #[arg(short, long, default_value_t = 1)]
+        controller: usize,
+        #[arg(long)]
+        start: Option<String>,
+        #[arg(long)]
+        end: Option<String>,
+    },
+    /// Runs a job on every movie in the given files and directories, in parallel
+    ///
+    /// Jobs are strip-plugins, fix-header, to-text (writing NAME.txt next to each movie),
+    /// stats and validate. Directories are searched for .m64 files, compressed or not.
+    Batch {
+        job: Job,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Shows what would change without writing anything
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Runs a Rhai script on a movie, printing its output
+    Script {
+        script: PathBuf,
+        movie: PathBuf,
+        /// Where to save the changed movie, which is otherwise discarded
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+fn open(path: &Path) -> Result<M64File> {
+    let data = if is_stdio(path) {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data)?;
+        data
+    } else {
+        read_file(path)?
+    };
+    let conversion = Format::of(path, &data).decode(&data)?;
+    for warning in conversion.warnings {
+        eprintln!("{}: {}", path.display(), warning);
+    }
+    Ok(conversion.movie)
+}
+
+fn write(movie: &M64File, path: &Path, format: Option<Format>) -> Result<()> {
+    let format = format.or_else(|| Format::from_path(path)).unwrap_or(Format::M64);
+    let conversion = format.encode(movie)?;
+    for warning in conversion.warnings {
+        eprintln!("{}: {}", path.display(), warning);
+    }
+    if is_stdio(path) {
+        io::stdout().lock().write_all(&conversion.movie)?;
+    } else {
+        save_file(path, &conversion.movie)?;
+    }
+    Ok(())
+}
+
+/// Frames from `start` to `end`, each defaulting to the start and end of the movie.
+fn parse_range(movie: &M64File, start: Option<&str>, end: Option<&str>) -> Result<Range<usize>> {
+    let timing = Timing::of(movie);
+    let frames = movie.frames();
+    let start = start.map_or(Ok(0), |start| timing.parse_frame(start))?;
+    let end = end.map_or(Ok(frames), |end| timing.parse_frame(end))?;
+    if start > end || end > frames {
+        return Err(anyhow!("Frames {}..{} are outside the movie's {} frames", start, end, frames));
+    }
+    Ok(start..end)
+}
+
+/// Replaces `range` of every connected controller with the frames of `source`, or with
+/// `count` neutral frames if there is no source, keeping the header in step.
+fn splice(movie: &mut M64File, range: Range<usize>, source: Option<&M64File>, count: usize) -> Result<()> {
+    let timing = Timing::of(movie);
+    let active = M64File::active_controllers(movie.controller_flags)?;
+    if let Some(source) = source
+        && M64File::active_controllers(source.controller_flags)? != active
+    {
+        return Err(anyhow!("The movies have different controllers connected"));
+    }
+    for &i in &active {
+        let new = match source {
+            Some(source) => source.inputs[i].clone(),
+            None => vec![Input::new(); count],
+        };
+        let end = range.end.min(movie.inputs[i].len());
+        movie.inputs[i].splice(range.start.min(end)..end, new);
+    }
+    let frames = movie.frames();
+    movie.num_samples = frames as u32;
+    movie.vi_count = timing.frame_to_vi(frames) as u32;
+    Ok(())
+}
+
+fn info(path: &Path, json: bool) -> Result<()> {
+    let movie = open(path)?;
+    let controllers: Vec<usize> = M64File::active_controllers(movie.controller_flags)
+        .unwrap_or_default()
+        .iter()
+        .map(|i| i + 1)
+        .collect();
+    let duration = timecode::duration(&movie);
+    if json {
+        let header: serde_json::Map<String, serde_json::Value> = movie.header_fields().into_iter()
+            .map(|(field, value)| (field.to_string(), value.into()))
+            .collect();
+        let info = json!({
+            "path": path,
+            "header": header,
+            "frames": movie.frames(),
+            "controllers": controllers,
+            "duration": duration,
+        });
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+    for (field, value) in movie.header_fields() {
+        println!("{:<18} {}", field, value);
+    }
+    println!();
+    println!("{:<18} {}", "frames", movie.frames());
+    println!("{:<18} {:?}", "controllers", controllers);
+    println!("{:<18} {}", "duration", format_time(duration));
+    Ok(())
+}
+
+/// Returns whether every movie is valid.
+fn validate(paths: &[PathBuf], json: bool) -> bool {
+    let results: Vec<(&PathBuf, Vec<String>)> = paths.iter()
+        .map(|path| (path, open(path).map_or_else(|e| vec![e.to_string()], |movie| movie.validate())))
+        .collect();
+    if json {
+        let results: Vec<_> = results.iter()
+            .map(|(path, problems)| json!({ "path": path, "valid": problems.is_empty(), "problems": problems }))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&results).unwrap());
+    } else {
+        for (path, problems) in &results {
+            if problems.is_empty() {
+                println!("{}: OK", path.display());
+            }
+            for problem in problems {
+                println!("{}: {}", path.display(), problem);
+            }
+        }
+    }
+    results.iter().all(|(_, problems)| problems.is_empty())
+}
+
+fn split(path: &Path, at: &[String], directory: Option<PathBuf>, format: Option<Format>) -> Result<()> {
+    let movie = open(path)?;
+    let timing = Timing::of(&movie);
+    let mut cuts = at.iter().map(|frame| timing.parse_frame(frame)).collect::<Result<Vec<_>>>()?;
+    cuts.sort();
+    cuts.dedup();
+    let format = format.unwrap_or(Format::M64);
+    let directory = directory.unwrap_or_else(|| path.parent().unwrap_or(Path::new("")).to_path_buf());
+    let name = match is_stdio(path) {
+        true => "movie".to_string(),
+        false => uncompressed_path(path).file_stem().unwrap_or_default().to_string_lossy().to_string(),
+    };
+    // Cut from the end, so that each cut is still at the same frame of what is left
+    let mut parts = Vec::new();
+    let mut rest = movie;
+    for &cut in cuts.iter().rev() {
+        let (first, last) = rest.split_at(cut)?;
+        parts.push(last);
+        rest = first;
+    }
+    parts.push(rest);
+    if cuts.iter().any(|&cut| cut > 0) {
+        eprintln!("Parts after the first start from a snapshot, which has to be saved at the frame they start on");
+    }
+    for (n, part) in parts.iter().rev().enumerate() {
+        write(part, &directory.join(format!("{}.{}.{}", name, n + 1, format.extension())), Some(format))?;
+    }
+    Ok(())
+}
+
+
+/// Prints each movie's outcome and a count of each status. Returns whether every movie was
+/// read, and written or validated, without errors.
+fn batch(job: Job, paths: &[PathBuf], dry_run: bool, json: bool) -> Result<bool> {
+    let mut movies = Vec::new();
+    for path in paths {
+        movies.extend(find_movies(path)?);
+    }
+    let outcomes = batch::run(job, &movies, dry_run);
+    let count = |status: Status| outcomes.iter().filter(|outcome| outcome.status == status).count();
+    let statuses = [Status::Changed, Status::Unchanged, Status::Invalid, Status::Failed];
+    if json {
+        let summary: serde_json::Map<String, serde_json::Value> = statuses.iter()
+            .map(|&status| (format!("{:?}", status).to_lowercase(), count(status).into()))
+            .collect();
+        let report = json!({ "job": job.name(), "dry_run": dry_run, "outcomes": outcomes, "summary": summary });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_outcomes(&outcomes, dry_run);
+        let counts: Vec<String> = statuses.iter()
+            .filter(|&&status| count(status) > 0)
+            .map(|&status| format!("{} {}", count(status), status.name(dry_run)))
+            .collect();
+        println!();
+        println!("{} movies: {}", outcomes.len(), counts.join(", "));
+    }
+    Ok(count(Status::Invalid) + count(Status::Failed) == 0)
+}
+
+fn print_outcomes(outcomes: &[Outcome], dry_run: bool) {
+    let paths: Vec<String> = outcomes.iter().map(|outcome| outcome.path.display().to_string()).collect();
+    let width = paths.iter().map(String::len).chain(["movie".len()]).max().unwrap_or(0);
+    println!("{:<width$}  {:<12}  notes", "movie", "status");
+    for (path, outcome) in paths.iter().zip(outcomes) {
+        let mut notes = outcome.notes.iter();
+        let first = notes.next().map_or("", String::as_str);
+        let row = format!("{:<width$}  {:<12}  {}", path, outcome.status.name(dry_run), first);
+        println!("{}", row.trim_end());
+        for note in notes {
+            println!("{:<width$}  {:<12}  {}", "", "", note);
+        }
+    }
+}
+
+fn main() -> Result<ExitCode> {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Info { movie } => info(&movie, cli.json)?,
+        Command::Validate { movies } => {
+            if !validate(&movies, cli.json) {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Trim { movie, start, end, output } => {
+            let mut movie = open(&movie)?;
+            let range = parse_range(&movie, start.as_deref(), end.as_deref())?;
+            let frames = movie.frames();
+            splice(&mut movie, range.end..frames, None, 0)?;
+            splice(&mut movie, 0..range.start, None, 0)?;
+            write(&movie, &output.output, output.format)?;
+        }
+        Command::Insert { movie, at, frames, from, output } => {
+            let mut movie = open(&movie)?;
+            let at = parse_range(&movie, Some(&at), None)?.start;
+            let source = from.map(|from| open(&from)).transpose()?;
+            splice(&mut movie, at..at, source.as_ref(), frames)?;
+            write(&movie, &output.output, output.format)?;
+        }
+        Command::Remove { movie, start, end, output } => {
+            let mut movie = open(&movie)?;
+            let range = parse_range(&movie, Some(&start), Some(&end))?;
+            splice(&mut movie, range, None, 0)?;
+            write(&movie, &output.output, output.format)?;
+        }
+        Command::Splice { movie, start, end, source, output } => {
+            let mut movie = open(&movie)?;
+            let range = parse_range(&movie, Some(&start), Some(&end))?;
+            splice(&mut movie, range, Some(&open(&source)?), 0)?;
+            write(&movie, &output.output, output.format)?;
+        }
+        Command::Concat { movies, output } => {
+            let segments = movies.iter().map(|movie| open(movie)).collect::<Result<Vec<_>>>()?;
+            write(&M64File::concat(&segments)?, &output.output, output.format)?;
+        }
+        Command::Split { movie, frames, directory, format } => split(&movie, &frames, directory, format)?,
+        Command::Convert { movie, output } => {
+            write(&open(&movie)?, &output.output, output.format)?;
+        }
+        Command::Diff { old, new } => {
+            let diff = diff(&open(&old)?, &open(&new)?);
+            if cli.json {
+                println!("{}", diff.to_json());
+            } else {
+                print!("{}", diff);
+            }
+        }
+        Command::Stats { movie, controller, start, end } => {
+            let movie = open(&movie)?;
+            if !(1..=4).contains(&controller) {
+                return Err(anyhow!("Invalid controller {}", controller));
+            }
+            let range = parse_range(&movie, start.as_deref(), end.as_deref())?;
+            let stats = stats(&movie.inputs[controller - 1], range);
+            if cli.json {
+                println!("{}", stats.to_json());
+            } else {
+                print!("{}", stats);
+            }
+        }
+        Command::Batch { job, paths, dry_run } => {
+            if !batch(job, &paths, dry_run, cli.json)? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Script { script, movie, output } => {
+            let mut movie = open(&movie)?;
+            print!("{}", run_script(&fs::read_to_string(script)?, &mut movie)?);
+            if let Some(output) = output {
+                write(&movie, &output, None)?;
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -1,16 +1,36 @@
-use crate::{AppState, OPEN_FILE, SET_OUTPUT_TEXT};
+use crate::api::file_handling::{backup_source, read_file, save_file_with_backups};
+use crate::api::format::Format;
+use crate::api::history::{Edit, History};
+use crate::api::journal::{self, Journal};
+use crate::api::m64_handling::M64File;
+use crate::widgets::rom_view::check_rom;
+use crate::{AppState, APPLY_EDIT, AUTOSAVE, OPEN_FILE, RECOVER_JOURNAL, REDO, RESTORE_BACKUP, SAVE_FILE, SAVE_STATS, SET_DIFF_FILE, SET_INPUT_FILE, SET_OUTPUT_TEXT, SET_ROM_FILE, SET_SCRIPT_FILE, UNDO};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
 use druid::{commands, AppDelegate, Command, DelegateCtx, Env, Event, Handled, LensExt, Target, WindowId};
 use druid_shell::RawMods::Ctrl;
 use druid_shell::{HotKey, KbKey};
 
+// Number of edits that can be undone
+const HISTORY_LIMIT: usize = 1000;
+
 pub struct Delegate {
     main_window: Option<WindowId>,
+    history: History,
+    // None until a movie is open, and while its recovery is being decided
+    journal: Option<Journal>,
+    // Movie recovered from the journal of the open movie, until it is accepted or discarded
+    recovery: Option<M64File>,
 }
 
 impl Delegate {
     pub fn new() -> Self {
         Self {
             main_window: None,
+            history: History::new(HISTORY_LIMIT),
+            journal: None,
+            recovery: None,
         }
     }
 
@@ -30,6 +50,146 @@ impl Delegate {
         self.main_window = None;
     }
 
+    fn open_movie(&mut self, path: &Path, data: &mut AppState) -> anyhow::Result<()> {
+        let movie = read_movie(path, path, data)?;
+        let recovery = journal::recover(path, &movie);
+        data.editor.load(movie);
+        data.input_m64 = path.to_string_lossy().to_string();
+        data.refresh_backups();
+        self.history.clear();
+        // Edits made before choosing whether to recover aren't journaled, so that the old
+        // journal is kept until then
+        self.journal = None;
+        self.recovery = None;
+        data.recovery_status.clear();
+        match recovery {
+            Ok(Some(recovery)) => {
+                data.recovery_status = describe_recovery(&recovery);
+                self.recovery = Some(recovery.movie);
+            }
+            Ok(None) => self.journal = Some(Journal::new(path)),
+            Err(e) => {
+                data.message = format!("Failed to read the journal: {}", e);
+                self.journal = Some(Journal::new(path));
+            }
+        }
+        // Warn straight away if the movie doesn't match the ROM already chosen
+        check_rom(data);
+        Ok(())
+    }
+
+    /// Replaces the open movie with the one recovered from its journal, or throws that away.
+    fn resolve_recovery(&mut self, data: &mut AppState, recover: bool) -> anyhow::Result<()> {
+        let mut journal = Journal::new(Path::new(&data.input_m64));
+        data.recovery_status.clear();
+        if let Some(recovered) = self.recovery.take().filter(|_| recover) {
+            if let Some(movie) = &data.editor.movie {
+                let edit = Edit::replace_movie(movie, &recovered);
+                self.apply_edit(data, edit)?;
+            }
+        }
+        // Edits made while the choice was pending weren't journaled, so the movie no longer
+        // matches the file and the journal has to start from a snapshot. The snapshot replaces
+        // the old journal in one step, so the recovered changes are never lost
+        match &data.editor.movie {
+            Some(movie) if recover || self.history.can_undo() => journal.snapshot(movie)?,
+            _ => journal.clear()?,
+        }
+        self.journal = Some(journal);
+        Ok(())
+    }
+
+    fn record(&mut self, data: &mut AppState, edit: &Edit) {
+        let Some(journal) = &mut self.journal else { return };
+        if let Err(e) = journal.record(edit) {
+            data.message = format!("Failed to write the journal: {}", e);
+        }
+    }
+
+    fn autosave(&mut self, data: &mut AppState) {
+        let (Some(journal), Some(movie)) = (&mut self.journal, &data.editor.movie) else { return };
+        if let Err(e) = journal.autosave(movie) {
+            data.message = format!("Failed to autosave: {}", e);
+        }
+    }
+
+    fn apply_edit(&mut self, data: &mut AppState, edit: Edit) -> anyhow::Result<()> {
+        let Some(movie) = &mut data.editor.movie else { return Ok(()) };
+        let recorded = self.journal.is_some().then(|| edit.clone());
+        self.history.apply(Arc::make_mut(movie), edit)?;
+        data.editor.clamp_selection();
+        if let Some(edit) = recorded {
+            self.record(data, &edit);
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self, data: &mut AppState, redo: bool) -> anyhow::Result<()> {
+        let Some(movie) = &mut data.editor.movie else { return Ok(()) };
+        // Check first so that an empty stack doesn't needlessly copy a shared movie
+        let mut applied = None;
+        if redo && self.history.can_redo() {
+            applied = self.history.next_redo().cloned();
+            self.history.redo(Arc::make_mut(movie))?;
+        } else if !redo && self.history.can_undo() {
+            applied = self.history.next_undo();
+            self.history.undo(Arc::make_mut(movie))?;
+        }
+        data.editor.clamp_selection();
+        if let Some(edit) = applied {
+            self.record(data, &edit);
+        }
+        Ok(())
+    }
+
+    /// Replaces the movie with a backup of it, as an edit that can be undone.
+    fn restore_backup(&mut self, backup: &Path, data: &mut AppState) -> anyhow::Result<()> {
+        // The backup is in the format of the file it was made from
+        let source = backup_source(backup).unwrap_or_else(|| data.save_path().to_path_buf());
+        let restored = read_movie(backup, &source, data)?;
+        let Some(movie) = &data.editor.movie else { return Ok(()) };
+        let edit = Edit::replace_movie(movie, &restored);
+        self.apply_edit(data, edit)?;
+        data.message = format!("Restored {}", backup.display());
+        Ok(())
+    }
+
+    fn save_movie(&mut self, data: &mut AppState) -> anyhow::Result<()> {
+        let Some(movie) = &data.editor.movie else { return Ok(()) };
+        let path = data.save_path().to_path_buf();
+        let conversion = Format::from_path(&path).unwrap_or(Format::M64).encode(movie)?;
+        data.message = conversion.warnings.join("; ");
+        save_file_with_backups(&path, &conversion.movie, data.backup_count)?;
+        data.refresh_backups();
+        // Changes saved elsewhere are still unsaved in the open movie
+        if path == Path::new(&data.input_m64) {
+            if let Some(journal) = &mut self.journal {
+                journal.clear()?;
+            }
+        }
+        Ok(())
+    }
+
+}
+
+fn describe_recovery(recovery: &journal::Recovery) -> String {
+    let mut status = if recovery.from_snapshot {
+        format!("Unsaved changes to this movie were found: an autosave and {} later edits", recovery.edits)
+    } else {
+        format!("Unsaved changes to this movie were found: {} edits", recovery.edits)
+    };
+    if recovery.skipped > 0 {
+        status.push_str(&format!(", {} more couldn't be read", recovery.skipped));
+    }
+    status
+}
+
+/// Reads the movie at `path`, in the format that `format_path` is named after.
+fn read_movie(path: &Path, format_path: &Path, data: &mut AppState) -> anyhow::Result<M64File> {
+    let bytes = read_file(path)?;
+    let conversion = Format::of(format_path, &bytes).decode(&bytes)?;
+    data.message = conversion.warnings.join("; ");
+    Ok(conversion.movie)
 }
 
 impl AppDelegate<String> for Delegate {
@@ -42,12 +202,10 @@ impl AppDelegate<String> for Delegate {
         _env: &Env,
     ) -> Handled {
         if let Some(_) = cmd.get(SET_OUTPUT_TEXT) {
-            println!("Show window");
             self.show_main(ctx);
             return Handled::Yes;
         }
         if let Some(_) = cmd.get(commands::CLOSE_ALL_WINDOWS) {
-            println!("Show window");
             self.close_all_windows(ctx);
             return Handled::Yes;
         }
@@ -66,17 +224,30 @@ impl AppDelegate<AppState> for Delegate {
     ) -> Option<Event> {
 
         if let Event::KeyDown(key_event) = &event {
-            println!("Key down: {:?}", key_event.key);
             if key_event.key == KbKey::Escape {
                 ctx.submit_command(commands::CLOSE_ALL_WINDOWS);
                 return None;
             }
             let open_file = HotKey::new(Ctrl, KbKey::Character("o".to_string()));
             if open_file.matches(key_event) {
-                println!("Open file");
                 ctx.submit_command(OPEN_FILE);
                 return None;
             }
+            let save_file = HotKey::new(Ctrl, KbKey::Character("s".to_string()));
+            if save_file.matches(key_event) {
+                ctx.submit_command(SAVE_FILE);
+                return None;
+            }
+            let undo = HotKey::new(Ctrl, KbKey::Character("z".to_string()));
+            if undo.matches(key_event) {
+                ctx.submit_command(UNDO);
+                return None;
+            }
+            let redo = HotKey::new(Ctrl, KbKey::Character("y".to_string()));
+            if redo.matches(key_event) {
+                ctx.submit_command(REDO);
+                return None;
+            }
         }
 
         Some(event)
@@ -91,15 +262,73 @@ impl AppDelegate<AppState> for Delegate {
     ) -> Handled {
         if let Some(info) = cmd.get(SET_OUTPUT_TEXT) {
             data.output_m64 = info.path().to_str().unwrap().to_string();
+            data.refresh_backups();
+            return Handled::Yes;
+        }
+        if let Some(info) = cmd.get(SET_DIFF_FILE) {
+            data.diff_m64 = info.path().to_string_lossy().to_string();
+            return Handled::Yes;
+        }
+        if let Some(info) = cmd.get(SET_ROM_FILE) {
+            data.rom_path = info.path().to_string_lossy().to_string();
+            check_rom(data);
+            return Handled::Yes;
+        }
+        if let Some(info) = cmd.get(SET_SCRIPT_FILE) {
+            data.script_path = info.path().to_string_lossy().to_string();
+            return Handled::Yes;
+        }
+        if let Some(info) = cmd.get(SET_INPUT_FILE) {
+            if let Err(e) = self.open_movie(info.path(), data) {
+                data.message = format!("Failed to open {}: {}", info.path().display(), e);
+            }
+            return Handled::Yes;
+        }
+        if let Some(recover) = cmd.get(RECOVER_JOURNAL) {
+            if let Err(e) = self.resolve_recovery(data, *recover) {
+                data.message = format!("Failed to recover: {}", e);
+            }
+            return Handled::Yes;
+        }
+        if cmd.is(AUTOSAVE) {
+            self.autosave(data);
+            return Handled::Yes;
+        }
+        if let Some(info) = cmd.get(RESTORE_BACKUP) {
+            if let Err(e) = self.restore_backup(info.path(), data) {
+                data.message = format!("Failed to restore {}: {}", info.path().display(), e);
+            }
+            return Handled::Yes;
+        }
+        if let Some(edit) = cmd.get(APPLY_EDIT).and_then(|edit| edit.take()) {
+            if let Err(e) = self.apply_edit(data, edit) {
+                data.message = format!("Failed to apply edit: {}", e);
+            }
+            return Handled::Yes;
+        }
+        if cmd.is(UNDO) || cmd.is(REDO) {
+            if let Err(e) = self.undo(data, cmd.is(REDO)) {
+                data.message = format!("Failed to undo: {}", e);
+            }
+            return Handled::Yes;
+        }
+        if let Some(info) = cmd.get(SAVE_STATS) {
+            if let Err(e) = fs::write(info.path(), &data.stats_json) {
+                data.message = format!("Failed to export statistics: {}", e);
+            }
+            return Handled::Yes;
+        }
+        if let Some(_) = cmd.get(SAVE_FILE) {
+            if let Err(e) = self.save_movie(data) {
+                data.message = format!("Failed to save: {}", e);
+            }
             return Handled::Yes;
         }
         if let Some(_) = cmd.get(commands::SHOW_WINDOW) {
-            println!("Show window");
             self.show_main(ctx);
             return Handled::Yes;
         }
         if let Some(_) = cmd.get(commands::CLOSE_ALL_WINDOWS) {
-            println!("Show window");
             self.close_all_windows(ctx);
             return Handled::Yes;
         }
@@ -0,0 +1,102 @@
+use crate::api::history::Edit;
+use crate::api::m64_handling::{Input, M64File};
+use crate::api::stick::{StickModel, Sm64Stick};
+use crate::APPLY_EDIT;
+use druid::{Data, EventCtx, Lens, SingleUse};
+use std::ops::Range;
+use std::sync::Arc;
+
+#[derive(Data, Clone, Lens)]
+pub struct EditorState {
+    pub movie: Option<Arc<M64File>>,
+    pub controller: usize,
+    // The frame the cursor is on; the selection spans from the anchor to the cursor
+    pub cursor: usize,
+    pub anchor: usize,
+    // Inputs of the range tools
+    pub tool_button: String,
+    pub tool_period: String,
+    pub tool_value: String,
+    pub search: String,
+    pub expression: String,
+    pub tool_status: String,
+    // Game model the grid shows effective stick values for
+    pub stick_model: Arc<dyn StickModel>,
+}
+
+impl EditorState {
+    pub fn new() -> EditorState {
+        EditorState {
+            movie: None,
+            controller: 0,
+            cursor: 0,
+            anchor: 0,
+            tool_button: "A".to_string(),
+            tool_period: "1".to_string(),
+            tool_value: "0".to_string(),
+            search: String::new(),
+            expression: String::new(),
+            tool_status: String::new(),
+            stick_model: Arc::new(Sm64Stick),
+        }
+    }
+
+    pub fn load(&mut self, movie: M64File) {
+        let active_controllers = M64File::active_controllers(movie.controller_flags).unwrap_or(vec![0]);
+        self.controller = active_controllers[0];
+        self.cursor = 0;
+        self.anchor = 0;
+        self.movie = Some(Arc::new(movie));
+    }
+
+    pub fn inputs(&self) -> &[Input] {
+        match &self.movie {
+            Some(movie) => &movie.inputs[self.controller],
+            None => &[],
+        }
+    }
+
+    pub fn input_at(&self, frame: usize) -> Option<&Input> {
+        self.inputs().get(frame)
+    }
+
+    /// The selected frames, clamped to the frames present in the current controller.
+    pub fn selection(&self) -> Range<usize> {
+        let len = self.inputs().len();
+        let start = self.cursor.min(self.anchor).min(len);
+        let end = (self.cursor.max(self.anchor) + 1).min(len);
+        start..end
+    }
+
+    pub fn select(&mut self, frame: usize, extend: bool) {
+        self.cursor = frame;
+        if !extend {
+            self.anchor = frame;
+        }
+    }
+
+    /// Keeps the cursor and anchor on existing frames after the movie has changed length.
+    pub fn clamp_selection(&mut self) {
+        let last = self.inputs().len().saturating_sub(1);
+        self.cursor = self.cursor.min(last);
+        self.anchor = self.anchor.min(last);
+    }
+
+    /// Builds an edit that applies `f` to the frames in `range` of the current controller.
+    pub fn edit_range(&self, range: Range<usize>, f: impl FnOnce(&mut [Input])) -> Option<Edit> {
+        let movie = self.movie.as_ref()?;
+        let mut inputs = self.inputs().get(range.clone())?.to_vec();
+        f(&mut inputs);
+        Some(Edit::replace(movie, self.controller, range.start, inputs))
+    }
+
+    pub fn edit_selection(&self, f: impl FnOnce(&mut [Input])) -> Option<Edit> {
+        self.edit_range(self.selection(), f)
+    }
+}
+
+pub fn submit_edit(ctx: &mut EventCtx, edit: Option<Edit>) {
+    if let Some(edit) = edit {
+        ctx.submit_command(APPLY_EDIT.with(SingleUse::new(edit)));
+    }
+}
@@ -0,0 +1,4 @@
+#![feature(ascii_char)]
+#![feature(int_roundings)]
+
+pub mod api;
\ No newline at end of file
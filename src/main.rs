@@ -2,21 +2,53 @@
 #![feature(int_roundings)]
 #![windows_subsystem = "windows"]
 
+use crate::api::file_handling;
+use crate::api::history::Edit;
+use crate::api::journal::AUTOSAVE_INTERVAL;
+use crate::api::merge::Merge;
+use crate::api::timecode::{self, format_time, Timing};
 use crate::delegate::Delegate;
+use crate::editor::{submit_edit, EditorState};
+use crate::widgets::backup_view::build_backup_panel;
+use crate::widgets::diff_view::build_diff_tab;
+use crate::widgets::grid::InputGrid;
+use crate::widgets::merge_view::build_merge_tab;
+use crate::widgets::rom_view::build_rom_panel;
+use crate::widgets::script_view::build_script_tab;
+use crate::widgets::stats_view::build_stats_tab;
+use crate::widgets::stick::StickEditor;
+use crate::widgets::tools::build_tools;
 use druid::widget::prelude::*;
-use druid::widget::{Align, Axis, Button, Controller, CrossAxisAlignment, Flex,
-                    Label, Tabs, TabsEdge, TabsPolicy, TabsTransition, TextBox, ViewSwitcher};
-use druid::{AppDelegate, AppLauncher, Data, FileDialogOptions, Lens, Selector, UnitPoint, Widget, WidgetExt, WindowDesc};
+use druid::widget::{Align, Axis, Button, Controller, CrossAxisAlignment, Either, Flex,
+                    Label, SizedBox, Tabs, TabsEdge, TabsPolicy, TabsTransition, TextBox, ViewSwitcher};
+use druid::{AppDelegate, AppLauncher, Data, FileDialogOptions, Lens, Selector, SingleUse, TimerToken, UnitPoint, Widget, WidgetExt, WindowDesc};
 use std::any::Any;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
 use druid_shell::FileSpec;
 
-mod api;
+use m64_editor::api;
+
 mod delegate;
+mod editor;
+mod widgets;
 
 pub const OPEN_FILE: Selector = Selector::new("app.open-file");
 pub const SET_OUTPUT_TEXT: Selector<druid_shell::FileInfo> = Selector::new("app.set-output-text");
+pub const SET_INPUT_FILE: Selector<druid_shell::FileInfo> = Selector::new("app.set-input-file");
+pub const SET_DIFF_FILE: Selector<druid_shell::FileInfo> = Selector::new("app.set-diff-file");
+pub const SET_ROM_FILE: Selector<druid_shell::FileInfo> = Selector::new("app.set-rom-file");
+pub const SET_SCRIPT_FILE: Selector<druid_shell::FileInfo> = Selector::new("app.set-script-file");
 pub const SAVE_FILE: Selector = Selector::new("app.save-file");
+pub const SAVE_STATS: Selector<druid_shell::FileInfo> = Selector::new("app.save-stats");
+pub const RECOVER_JOURNAL: Selector<bool> = Selector::new("app.recover-journal");
+pub const AUTOSAVE: Selector = Selector::new("app.autosave");
+pub const RESTORE_BACKUP: Selector<druid_shell::FileInfo> = Selector::new("app.restore-backup");
 pub const QUIT_APP: Selector = Selector::new("app.quit-app");
+pub const APPLY_EDIT: Selector<SingleUse<Edit>> = Selector::new("app.apply-edit");
+pub const UNDO: Selector = Selector::new("app.undo");
+pub const REDO: Selector = Selector::new("app.redo");
 
 #[derive(Data, Clone, Lens)]
 struct TabConfig {
@@ -37,9 +69,50 @@ struct AppState {
     input_end: String,
     output_start: String,
     output_end: String,
+    editor: EditorState,
+    diff_m64: String,
+    diff_output: String,
+    merge_base: String,
+    merge_theirs: String,
+    merge_status: String,
+    merge: Option<Arc<Merge>>,
+    merge_index: usize,
+    script_path: String,
+    script_output: String,
+    stats_output: String,
+    stats_json: String,
+    rom_path: String,
+    rom_status: String,
+    backup_count: usize,
+    // Names of the backups of the file that saving writes to, read again after each save
+    backups: Arc<Vec<String>>,
+    // Describes unsaved changes found in the journal of the open movie, while they can be recovered
+    recovery_status: String,
+    // Warnings from the last conversion, shown in the status bar
+    message: String,
 }
 
 
+/// Asks the delegate to autosave the open movie every [`AUTOSAVE_INTERVAL`].
+struct AutosaveTimer {
+    timer: TimerToken,
+}
+
+impl<W: Widget<AppState>> Controller<AppState, W> for AutosaveTimer {
+    fn event(&mut self, child: &mut W, ctx: &mut EventCtx, event: &Event, data: &mut AppState, env: &Env) {
+        match event {
+            Event::WindowConnected => self.timer = ctx.request_timer(AUTOSAVE_INTERVAL),
+            Event::Timer(token) if *token == self.timer => {
+                ctx.submit_command(AUTOSAVE);
+                self.timer = ctx.request_timer(AUTOSAVE_INTERVAL);
+                return;
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
 fn build_root_widget() -> impl Widget<AppState> {
     fn group<T: Data, W: Widget<T> + 'static>(text: &str, w: W) -> impl Widget<T> {
         Flex::row()
@@ -55,7 +128,84 @@ fn build_root_widget() -> impl Widget<AppState> {
             transition: TabsTransition::Instant,
         })),
     );
-    Flex::column().with_flex_child(vs, 1.0).fix_width(400.0)
+    let status_bar = Label::dynamic(|data: &AppState, _| data.status())
+        .with_text_size(12.0)
+        .padding((5.0, 2.0))
+        .align_left();
+    let recovery_bar = Flex::row()
+        .with_flex_child(Label::dynamic(|data: &AppState, _| data.recovery_status.clone()).align_left(), 1.0)
+        .with_spacer(10.0)
+        .with_child(Button::new("Recover").on_click(|ctx, _data: &mut AppState, _| {
+            ctx.submit_command(RECOVER_JOURNAL.with(true))
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("Discard").on_click(|ctx, _data: &mut AppState, _| {
+            ctx.submit_command(RECOVER_JOURNAL.with(false))
+        }))
+        .padding((5.0, 2.0));
+    let recovery_bar = Either::new(|data: &AppState, _| !data.recovery_status.is_empty(), recovery_bar, SizedBox::empty());
+    Flex::column()
+        .with_flex_child(vs, 1.0)
+        .with_child(recovery_bar)
+        .with_child(status_bar)
+        .fix_width(840.0)
+        .controller(AutosaveTimer { timer: TimerToken::INVALID })
+}
+
+impl AppState {
+    /// Where saving writes the movie: the output file if one is set, otherwise the movie itself.
+    fn save_path(&self) -> &Path {
+        Path::new(if self.output_m64.is_empty() { &self.input_m64 } else { &self.output_m64 })
+    }
+
+    /// Reads the list of backups again, as saving or changing the output file changes it.
+    fn refresh_backups(&mut self) {
+        let backups = match self.input_m64.is_empty() {
+            true => Vec::new(),
+            false => file_handling::backups(self.save_path()).iter()
+                .filter_map(|backup| Some(backup.file_name()?.to_string_lossy().to_string()))
+                .collect(),
+        };
+        self.backups = Arc::new(backups);
+    }
+
+    fn timing(&self) -> Option<Timing> {
+        self.editor.movie.as_ref().map(|movie| Timing::of(movie))
+    }
+
+    /// Frames to erase, from the first and last frame boxes, which accept times and sums.
+    fn erase_range(&self) -> anyhow::Result<Range<usize>> {
+        let movie = self.editor.movie.as_ref().ok_or(anyhow::anyhow!("No movie loaded"))?;
+        let timing = Timing::of(movie);
+        let first = timing.parse_frame(&self.input_start)?;
+        let last = timing.parse_frame(&self.input_end)?;
+        if last < first {
+            anyhow::bail!("The last frame {} is before the first frame {}", last, first);
+        }
+        if last >= movie.frames() {
+            anyhow::bail!("The movie only has {} frames", movie.frames());
+        }
+        Ok(first..last + 1)
+    }
+
+    fn duration_text(&self) -> Option<String> {
+        let movie = self.editor.movie.as_ref()?;
+        let timing = Timing::of(movie);
+        Some(format!(
+            "{} ({} frames, {} VIs at {} VI/s)",
+            format_time(timecode::duration(movie)),
+            movie.num_samples,
+            movie.vi_count,
+            timing.vi_per_second
+        ))
+    }
+
+    fn status(&self) -> String {
+        let Some(duration) = self.duration_text() else { return "No movie loaded".to_string() };
+        let cursor = self.timing().map_or(0.0, |timing| timing.frame_to_seconds(self.editor.cursor));
+        let status = format!("{}  |  {}  |  Frame {} at {}", self.input_m64, duration, self.editor.cursor, format_time(cursor));
+        if self.message.is_empty() { status } else { format!("{}\n{}", status, self.message) }
+    }
 }
 
 
@@ -63,22 +213,39 @@ fn build_tab_widget(tab_config: &TabConfig) -> impl Widget<AppState> {
     let control_dynamic = Flex::column()
         .cross_axis_alignment(CrossAxisAlignment::Start)
         .with_child(Label::new("Control dynamic tabs"))
-        .with_spacer(20.).with_spacer(20.);
+        .with_spacer(20.).with_spacer(20.)
+        .with_child(build_backup_panel());
 
     let first_static_tab = Flex::column()
         .with_flex_child(Flex::row()
                              .with_child(Label::new("Rename tab:")), 1.0)
-        .with_child(TextBox::new().lens(AppState::first_tab_name));
+        .with_child(TextBox::new().lens(AppState::first_tab_name))
+        .with_spacer(20.0)
+        .with_child(Label::dynamic(|data: &AppState, _| match data.duration_text() {
+            Some(duration) => format!("Duration: {}", duration),
+            None => "No movie loaded".to_string(),
+        }))
+        .with_spacer(20.0)
+        .with_child(build_rom_panel());
 
     let m64_spec = FileSpec::new("M64 files", &["m64"]);
+    let bk2_spec = FileSpec::new("BizHawk movies", &["bk2"]);
+    let text_spec = FileSpec::new("Text movies", &["txt"]);
+    let table_spec = FileSpec::new("Input tables", &["csv", "json"]);
+    let compressed_spec = FileSpec::new("Compressed movies", &["gz", "zst"]);
     let save_dialog_options = FileDialogOptions::new()
         .accept_command(druid::commands::OPEN_FILE)
-        .allowed_types(vec![m64_spec])
+        .allowed_types(vec![m64_spec, bk2_spec, text_spec, table_spec, compressed_spec])
         .default_type(m64_spec)
         .default_name("output.m64".to_string())
         .name_label("Target")
         .title("Choose a target for this lovely file")
         .button_text("Export");
+    let open_dialog_options = FileDialogOptions::new()
+        .allowed_types(vec![m64_spec, bk2_spec, text_spec, table_spec, compressed_spec])
+        .default_type(m64_spec)
+        .title("Choose a movie to edit")
+        .accept_command(SET_INPUT_FILE);
     let replacement_tab = {
         let input_m64_row = Flex::row()
             .with_child(
@@ -101,8 +268,8 @@ fn build_tab_widget(tab_config: &TabConfig) -> impl Widget<AppState> {
             .with_child(
                 Button::new("...")
                     .fix_height(26.0)
-                    .on_click(|ctx, data: &mut AppState, _| {
-                        data.output_m64 = data.input_m64.clone();
+                    .on_click(move |ctx, _data: &mut AppState, _| {
+                        ctx.submit_command(druid::commands::SHOW_OPEN_PANEL.with(open_dialog_options.clone()))
                     })
             );
 
@@ -172,7 +339,15 @@ fn build_tab_widget(tab_config: &TabConfig) -> impl Widget<AppState> {
             .with_spacer(10.0)
             .with_child(TextBox::new()
                 .lens(AppState::input_end)
-                .fix_width(50.0));
+                .fix_width(50.0))
+            .with_spacer(10.0)
+            .with_child(Button::new("Erase").on_click(|ctx, data: &mut AppState, _| {
+                match (data.erase_range(), &data.editor.movie) {
+                    (Ok(range), Some(movie)) => submit_edit(ctx, Some(Edit::remove(movie, range))),
+                    (Err(e), _) => data.message = format!("Failed to erase: {}", e),
+                    _ => {}
+                }
+            }));
 
         let container = Flex::column()
             .with_child(m64_col)
@@ -182,16 +357,45 @@ fn build_tab_widget(tab_config: &TabConfig) -> impl Widget<AppState> {
                 .with_child(Label::new("Part to erase from base file:"))
                 .align_left())
             .with_child(erase_row)
+            .with_spacer(4.0)
+            .with_child(Label::dynamic(|data: &AppState, _| {
+                if data.input_start.is_empty() && data.input_end.is_empty() {
+                    return "Frames can be given as times and sums, e.g. 1:23.40 or 5000+120".to_string();
+                }
+                match data.erase_range() {
+                    Ok(range) => format!("Frames {}-{}", range.start, range.end - 1),
+                    Err(e) => e.to_string(),
+                }
+            }).align_left())
             .padding(15.0);
         container
     };
+    let inputs_tab = Flex::row()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Flex::column()
+            .with_child(InputGrid::header())
+            .with_flex_child(InputGrid::new().scroll().vertical(), 1.0))
+        .with_spacer(10.0)
+        .with_child(Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(StickEditor::new())
+            .with_spacer(10.0)
+            .with_child(build_tools()))
+        .padding(5.0)
+        .lens(AppState::editor);
+
     let main_tabs = Tabs::new()
         .with_axis(tab_config.axis)
         .with_edge(tab_config.edge)
         .with_transition(tab_config.transition)
         .with_tab("Header", first_static_tab)
         .with_tab("Settings", control_dynamic)
-        .with_tab("Replacement", replacement_tab);
+        .with_tab("Replacement", replacement_tab)
+        .with_tab("Inputs", inputs_tab)
+        .with_tab("Diff", build_diff_tab())
+        .with_tab("Merge", build_merge_tab())
+        .with_tab("Stats", build_stats_tab())
+        .with_tab("Script", build_script_tab());
 
     Align::left(main_tabs)
 }
@@ -201,7 +405,7 @@ pub fn main() {
     // describe the main window
     let main_window = WindowDesc::new(build_root_widget())
         .title("M64 Editor v2.0")
-        .window_size((550.0, 400.0))
+        .window_size((880.0, 560.0))
         .resizable(false);
 
     // create the initial app state
@@ -219,6 +423,24 @@ pub fn main() {
         input_end: String::new(),
         output_start: String::new(),
         output_end: String::new(),
+        editor: EditorState::new(),
+        diff_m64: String::new(),
+        diff_output: String::new(),
+        merge_base: String::new(),
+        merge_theirs: String::new(),
+        merge_status: String::new(),
+        merge: None,
+        merge_index: 0,
+        script_path: String::new(),
+        script_output: String::new(),
+        stats_output: String::new(),
+        stats_json: String::new(),
+        rom_path: String::new(),
+        rom_status: String::new(),
+        backup_count: 3,
+        backups: Arc::new(Vec::new()),
+        recovery_status: String::new(),
+        message: String::new(),
     };
 
     // start the application
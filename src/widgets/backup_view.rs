@@ -0,0 +1,72 @@
+use crate::api::file_handling::remove_old_backups;
+use crate::{AppState, RESTORE_BACKUP};
+use druid::widget::{Button, Controller, CrossAxisAlignment, Flex, Label, LineBreaking, Stepper};
+use druid::{Env, Event, EventCtx, FileDialogOptions, LensExt, Widget, WidgetExt};
+use druid_shell::FileSpec;
+
+// Most backups that can be kept of each movie
+const MAX_BACKUPS: f64 = 20.0;
+
+/// Sets how many backups saving keeps, and restores the movie from one of them.
+pub fn build_backup_panel() -> impl Widget<AppState> {
+    let count_row = Flex::row()
+        .with_child(Label::new("Backups kept on save:"))
+        .with_spacer(10.0)
+        .with_child(Label::dynamic(|count: &usize, _| count.to_string()).lens(AppState::backup_count))
+        .with_spacer(4.0)
+        .with_child(
+            Stepper::new()
+                .with_range(0.0, MAX_BACKUPS)
+                .with_step(1.0)
+                .lens(AppState::backup_count.map(|count| *count as f64, |count, value| *count = value as usize))
+                .controller(RemoveOldBackups),
+        );
+
+    let restore_button = Button::new("Restore from backup...").on_click(|ctx, data: &mut AppState, _| {
+        let backup_spec = FileSpec::new("Backups", &["bak"]);
+        let mut options = FileDialogOptions::new()
+            .allowed_types(vec![backup_spec])
+            .default_type(backup_spec)
+            .title("Choose a backup to restore")
+            .accept_command(RESTORE_BACKUP);
+        if let Some(directory) = data.save_path().parent() {
+            options = options.force_starting_directory(directory);
+        }
+        ctx.submit_command(druid::commands::SHOW_OPEN_PANEL.with(options))
+    });
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(count_row)
+        .with_spacer(10.0)
+        .with_child(
+            Label::dynamic(|data: &AppState, _| {
+                if data.input_m64.is_empty() {
+                    "No movie loaded".to_string()
+                } else if data.backups.is_empty() {
+                    "No backups of this movie".to_string()
+                } else {
+                    format!("Backups: {}", data.backups.join(", "))
+                }
+            })
+            .with_line_break_mode(LineBreaking::WordWrap),
+        )
+        .with_spacer(4.0)
+        .with_child(restore_button)
+}
+
+/// Removes the backups past the new count when fewer backups are to be kept.
+struct RemoveOldBackups;
+
+impl<W: Widget<AppState>> Controller<AppState, W> for RemoveOldBackups {
+    fn event(&mut self, child: &mut W, ctx: &mut EventCtx, event: &Event, data: &mut AppState, env: &Env) {
+        let count = data.backup_count;
+        child.event(ctx, event, data, env);
+        if data.backup_count < count && !data.input_m64.is_empty() {
+            if let Err(e) = remove_old_backups(data.save_path(), data.backup_count) {
+                data.message = format!("Failed to remove old backups: {}", e);
+            }
+            data.refresh_backups();
+        }
+    }
+}
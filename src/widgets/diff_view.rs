@@ -0,0 +1,56 @@
+use crate::api::diff::diff;
+use crate::api::format::read_movie;
+use crate::{AppState, SET_DIFF_FILE};
+use druid::widget::{Button, CrossAxisAlignment, Flex, Label, LineBreaking, TextBox};
+use druid::{FileDialogOptions, FontDescriptor, FontFamily, Widget, WidgetExt};
+use druid_shell::FileSpec;
+use std::path::Path;
+
+/// Compares the movie being edited with another movie on disk.
+pub fn build_diff_tab() -> impl Widget<AppState> {
+    let movie_spec = FileSpec::new("Movies", &["m64", "bk2", "txt", "csv", "json", "gz", "zst"]);
+    let open_dialog_options = FileDialogOptions::new()
+        .allowed_types(vec![movie_spec])
+        .default_type(movie_spec)
+        .title("Choose a movie to compare with")
+        .accept_command(SET_DIFF_FILE);
+
+    let path_row = Flex::row()
+        .with_child(Label::new("Compare with:"))
+        .with_spacer(10.0)
+        .with_flex_child(TextBox::new().lens(AppState::diff_m64).expand_width(), 1.0)
+        .with_spacer(10.0)
+        .with_child(Button::new("...").on_click(move |ctx, _data: &mut AppState, _| {
+            ctx.submit_command(druid::commands::SHOW_OPEN_PANEL.with(open_dialog_options.clone()))
+        }))
+        .with_spacer(10.0)
+        .with_child(Button::new("Compare").on_click(|_, data: &mut AppState, _| {
+            data.diff_output = match compare(data) {
+                Ok(output) => output,
+                Err(e) => format!("Failed to compare: {}", e),
+            };
+        }));
+
+    let output = Label::dynamic(|output: &String, _| output.clone())
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .lens(AppState::diff_output)
+        .scroll()
+        .vertical()
+        .expand();
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(path_row)
+        .with_spacer(10.0)
+        .with_flex_child(output, 1.0)
+        .padding(15.0)
+}
+
+fn compare(data: &AppState) -> anyhow::Result<String> {
+    let Some(movie) = &data.editor.movie else { return Ok("No movie loaded".to_string()) };
+    let other = read_movie(Path::new(&data.diff_m64))?;
+    let mut output: String = other.warnings.iter().map(|warning| format!("Warning: {}\n", warning)).collect();
+    output.push_str(&diff(movie, &other.movie).to_string());
+    Ok(output)
+}
@@ -0,0 +1,269 @@
+use crate::api::history::Edit;
+use crate::api::input_text;
+use crate::api::m64_handling::{Button, Input};
+use crate::api::patterns;
+use crate::editor::{submit_edit, EditorState};
+use druid::kurbo::Line;
+use druid::piet::{FontFamily, Text, TextLayoutBuilder};
+use druid::widget::prelude::*;
+use druid::widget::Painter;
+use druid::{Application, Color, KbKey, Point, Rect, WidgetExt};
+use druid_shell::{HotKey, RawMods};
+use std::ops::Range;
+
+pub const ROW_HEIGHT: f64 = 18.0;
+const FRAME_WIDTH: f64 = 64.0;
+// Wide enough for the raw value followed by the effective value
+const STICK_WIDTH: f64 = 72.0;
+const BUTTON_WIDTH: f64 = 24.0;
+const GRID_WIDTH: f64 = FRAME_WIDTH + 2.0 * STICK_WIDTH + 14.0 * BUTTON_WIDTH;
+
+/// Frame-by-frame view of the current controller's inputs.
+///
+/// Clicking a row moves the cursor, shift-click extends the selection and clicking or dragging
+/// over a button cell paints that button. Insert adds blank frames before the selection and
+/// Delete removes the selected frames.
+///
+/// Ctrl+C copies the selection as text, Ctrl+V inserts the clipboard before the selection and
+/// Ctrl+Shift+V overwrites from the start of the selection.
+pub struct InputGrid {
+    painting: Option<Paint>,
+}
+
+// A button being painted over the frames between the press and the current mouse position
+#[derive(Clone, Copy)]
+struct Paint {
+    button: Button,
+    pressed: bool,
+    from: usize,
+    to: usize,
+}
+
+impl Paint {
+    fn range(&self) -> Range<usize> {
+        self.from.min(self.to)..self.from.max(self.to) + 1
+    }
+}
+
+enum Column {
+    Frame,
+    Stick,
+    Button(Button),
+}
+
+impl InputGrid {
+    pub fn new() -> InputGrid {
+        InputGrid { painting: None }
+    }
+
+    /// Column labels, meant to sit above the scrolled grid.
+    pub fn header() -> impl Widget<EditorState> {
+        Painter::new(|ctx, _data: &EditorState, _env| {
+            let labels = ["Frame", "X", "Y"].into_iter().chain(Button::ALL.iter().map(Button::label));
+            for (i, label) in labels.enumerate() {
+                paint_text(ctx, label, Point::new(column_left(i) + 2.0, 2.0), &Color::grey8(0xC0));
+            }
+        })
+        .fix_size(GRID_WIDTH, ROW_HEIGHT)
+    }
+
+    fn column_at(x: f64) -> Column {
+        if x < FRAME_WIDTH + 2.0 * STICK_WIDTH {
+            return if x < FRAME_WIDTH { Column::Frame } else { Column::Stick };
+        }
+        let i = ((x - FRAME_WIDTH - 2.0 * STICK_WIDTH) / BUTTON_WIDTH) as usize;
+        Column::Button(Button::ALL[i.min(Button::ALL.len() - 1)])
+    }
+
+    fn frame_at(y: f64, data: &EditorState) -> Option<usize> {
+        let frame = (y / ROW_HEIGHT).max(0.0) as usize;
+        (frame < data.inputs().len()).then_some(frame)
+    }
+
+    pub fn copy(data: &EditorState) {
+        let text = input_text::encode(&data.inputs()[data.selection()]);
+        Application::global().clipboard().put_string(text);
+    }
+
+    /// Frames on the clipboard, if it holds text in the input format. Otherwise the reason is
+    /// shown in the tool status.
+    pub fn clipboard_inputs(data: &mut EditorState) -> Option<Vec<Input>> {
+        let text = Application::global().clipboard().get_string()?;
+        match input_text::decode(&text) {
+            Ok(inputs) => Some(inputs),
+            Err(e) => {
+                data.tool_status = format!("Failed to paste: {}", e);
+                None
+            }
+        }
+    }
+
+    fn paste(data: &mut EditorState, overwrite: bool) -> Option<Edit> {
+        let pasted = Self::clipboard_inputs(data)?;
+        let movie = data.movie.as_ref()?;
+        let start = data.selection().start;
+        let edit = if overwrite {
+            Edit::overwrite(movie, data.controller, start, pasted)
+        } else {
+            Edit::insert_inputs(movie, start, data.controller, pasted)
+        };
+        edit.map_err(|e| data.tool_status = format!("Failed to paste: {}", e)).ok()
+    }
+}
+
+fn column_left(i: usize) -> f64 {
+    match i {
+        0 => 0.0,
+        1 | 2 => FRAME_WIDTH + (i - 1) as f64 * STICK_WIDTH,
+        _ => FRAME_WIDTH + 2.0 * STICK_WIDTH + (i - 3) as f64 * BUTTON_WIDTH,
+    }
+}
+
+fn row_rect(frame: usize) -> Rect {
+    Rect::new(0.0, frame as f64 * ROW_HEIGHT, GRID_WIDTH, (frame + 1) as f64 * ROW_HEIGHT)
+}
+
+fn paint_text(ctx: &mut PaintCtx, text: &str, pos: Point, color: &Color) {
+    let layout = ctx.text()
+        .new_text_layout(text.to_string())
+        .font(FontFamily::MONOSPACE, 11.0)
+        .text_color(color.clone())
+        .build()
+        .unwrap();
+    ctx.draw_text(&layout, pos);
+}
+
+impl Widget<EditorState> for InputGrid {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut EditorState, _env: &Env) {
+        match event {
+            Event::MouseDown(mouse) => {
+                ctx.request_focus();
+                let Some(frame) = Self::frame_at(mouse.pos.y, data) else { return };
+                match Self::column_at(mouse.pos.x) {
+                    Column::Button(button) if !mouse.mods.shift() => {
+                        let pressed = !data.inputs()[frame].pressed(button);
+                        self.painting = Some(Paint { button, pressed, from: frame, to: frame });
+                        data.select(frame, false);
+                    }
+                    _ => data.select(frame, mouse.mods.shift()),
+                }
+                ctx.set_active(true);
+            }
+            Event::MouseMove(mouse) if ctx.is_active() => {
+                if let Some(frame) = Self::frame_at(mouse.pos.y, data) {
+                    if let Some(paint) = &mut self.painting {
+                        paint.to = frame;
+                    }
+                    data.select(frame, true);
+                }
+            }
+            Event::MouseUp(_) if ctx.is_active() => {
+                ctx.set_active(false);
+                if let Some(paint) = self.painting.take() {
+                    let edit = data.edit_range(paint.range(), |inputs| match paint.pressed {
+                        true => patterns::hold(inputs, paint.button),
+                        false => patterns::release(inputs, paint.button),
+                    });
+                    submit_edit(ctx, edit);
+                }
+            }
+            Event::KeyDown(key) if HotKey::new(RawMods::Ctrl, KbKey::Character("c".to_string())).matches(key) => {
+                Self::copy(data);
+                ctx.set_handled();
+            }
+            Event::KeyDown(key) if HotKey::new(RawMods::Ctrl, KbKey::Character("v".to_string())).matches(key) => {
+                submit_edit(ctx, Self::paste(data, false));
+                ctx.set_handled();
+            }
+            Event::KeyDown(key) if HotKey::new(RawMods::CtrlShift, KbKey::Character("V".to_string())).matches(key) => {
+                submit_edit(ctx, Self::paste(data, true));
+                ctx.set_handled();
+            }
+            Event::KeyDown(key) if key.key == KbKey::Insert => {
+                let selection = data.selection();
+                let edit = data.movie.as_ref().and_then(|movie| Edit::insert(movie, selection.start, selection.len().max(1)).ok());
+                submit_edit(ctx, edit);
+                ctx.set_handled();
+            }
+            Event::KeyDown(key) if key.key == KbKey::Delete => {
+                let edit = data.movie.as_ref().map(|movie| Edit::remove(movie, data.selection()));
+                submit_edit(ctx, edit);
+                ctx.set_handled();
+            }
+            Event::KeyDown(key) => {
+                let last = data.inputs().len().saturating_sub(1);
+                let frame = match key.key {
+                    KbKey::ArrowUp => data.cursor.saturating_sub(1),
+                    KbKey::ArrowDown => (data.cursor + 1).min(last),
+                    KbKey::PageUp => data.cursor.saturating_sub(20),
+                    KbKey::PageDown => (data.cursor + 20).min(last),
+                    KbKey::Home => 0,
+                    KbKey::End => last,
+                    _ => return,
+                };
+                data.select(frame, key.mods.shift());
+                ctx.set_handled();
+            }
+            _ => {}
+        }
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, _data: &EditorState, _env: &Env) {
+        if let LifeCycle::BuildFocusChain = event {
+            ctx.register_for_focus();
+        }
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &EditorState, data: &EditorState, _env: &Env) {
+        if old_data.inputs().len() != data.inputs().len() {
+            ctx.request_layout();
+        }
+        if old_data.cursor != data.cursor {
+            ctx.scroll_area_to_view(row_rect(data.cursor));
+        }
+        if !old_data.same(data) {
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(&mut self, _ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &EditorState, _env: &Env) -> Size {
+        bc.constrain(Size::new(GRID_WIDTH, data.inputs().len().max(1) as f64 * ROW_HEIGHT))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &EditorState, _env: &Env) {
+        let visible = ctx.region().bounding_box();
+        let inputs = data.inputs();
+        let first = (visible.y0 / ROW_HEIGHT).floor().max(0.0) as usize;
+        let last = ((visible.y1 / ROW_HEIGHT).ceil() as usize).min(inputs.len());
+        let selection = data.selection();
+        let grid = Color::grey8(0x40);
+
+        for frame in first..last {
+            let mut input = inputs[frame].clone();
+            if let Some(paint) = self.painting.filter(|paint| paint.range().contains(&frame)) {
+                input.set(paint.button, paint.pressed);
+            }
+            let row = row_rect(frame);
+            if selection.contains(&frame) {
+                ctx.fill(row, &Color::rgb8(0x30, 0x40, 0x60));
+            }
+            if frame == data.cursor {
+                ctx.stroke(row.inset(-0.5), &Color::rgb8(0x80, 0xA0, 0xFF), 1.0);
+            }
+            let y = row.y0 + 2.0;
+            paint_text(ctx, &frame.to_string(), Point::new(2.0, y), &Color::grey8(0xA0));
+            paint_text(ctx, &format!("{:4}", input.x), Point::new(column_left(1) + 2.0, y), &Color::WHITE);
+            paint_text(ctx, &format!("{:4}", input.y), Point::new(column_left(2) + 2.0, y), &Color::WHITE);
+            let (effective_x, effective_y) = data.stick_model.effective(input.x, input.y);
+            let effective = Color::grey8(0x90);
+            paint_text(ctx, &format!("{:6.1}", effective_x), Point::new(column_left(1) + 30.0, y), &effective);
+            paint_text(ctx, &format!("{:6.1}", effective_y), Point::new(column_left(2) + 30.0, y), &effective);
+            for (i, button) in Button::ALL.into_iter().enumerate() {
+                if input.pressed(button) {
+                    paint_text(ctx, button.label(), Point::new(column_left(i + 3) + 2.0, y), &Color::rgb8(0xFF, 0xD0, 0x40));
+                }
+            }
+            ctx.stroke(Line::new((0.0, row.y1), (GRID_WIDTH, row.y1)), &grid, 0.5);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::api::format;
+use crate::api::history::Edit;
+use crate::api::input_text;
+use crate::api::m64_handling::M64File;
+use crate::api::merge::{merge, Merge, Side};
+use crate::editor::submit_edit;
+use crate::AppState;
+use druid::widget::{Button, CrossAxisAlignment, Flex, Label, TextBox};
+use druid::{FontDescriptor, FontFamily, Widget, WidgetExt};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Three-way merge of the movie being edited ("ours") with another branch ("theirs") of a
+/// common base movie, stepping through the conflicts one at a time.
+pub fn build_merge_tab() -> impl Widget<AppState> {
+    let paths = Flex::column()
+        .with_child(path_row("Base M64:", TextBox::new().lens(AppState::merge_base)))
+        .with_spacer(4.0)
+        .with_child(path_row("Their M64:", TextBox::new().lens(AppState::merge_theirs)));
+
+    let merge_row = Flex::row()
+        .with_child(Button::new("Merge").on_click(|_, data: &mut AppState, _| {
+            let mut warnings = Vec::new();
+            match start_merge(data, &mut warnings) {
+                Ok(merge) => {
+                    data.merge_status = summary(&merge);
+                    for warning in warnings {
+                        data.merge_status.push_str(&format!("; {}", warning));
+                    }
+                    data.merge = Some(Arc::new(merge));
+                    data.merge_index = 0;
+                }
+                Err(e) => data.merge_status = format!("Failed to merge: {}", e),
+            }
+        }))
+        .with_spacer(10.0)
+        .with_flex_child(Label::dynamic(|status: &String, _| status.clone()).lens(AppState::merge_status), 1.0);
+
+    let conflict_row = Flex::row()
+        .with_child(Button::new("<").on_click(|_, data: &mut AppState, _| {
+            data.merge_index = data.merge_index.saturating_sub(1);
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new(">").on_click(|_, data: &mut AppState, _| {
+            let count = data.merge.as_ref().map_or(0, |merge| merge.conflicts.len());
+            data.merge_index = (data.merge_index + 1).min(count.saturating_sub(1));
+        }))
+        .with_spacer(10.0)
+        .with_child(Button::new("Take ours").on_click(|_, data: &mut AppState, _| resolve(data, Side::Ours)))
+        .with_spacer(4.0)
+        .with_child(Button::new("Take theirs").on_click(|_, data: &mut AppState, _| resolve(data, Side::Theirs)))
+        .with_spacer(10.0)
+        .with_child(Button::new("Apply merge").on_click(|ctx, data: &mut AppState, _| {
+            if let (Some(movie), Some(merge)) = (&data.editor.movie, &data.merge) {
+                submit_edit(ctx, Some(Edit::replace_movie(movie, &merge.to_movie())));
+                data.merge = None;
+                data.merge_status = "Merge applied".to_string();
+            }
+        }));
+
+    let conflict = Label::dynamic(|data: &AppState, _| describe_conflict(data))
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+        .scroll()
+        .vertical()
+        .expand();
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(paths)
+        .with_spacer(10.0)
+        .with_child(merge_row)
+        .with_spacer(10.0)
+        .with_child(conflict_row)
+        .with_spacer(10.0)
+        .with_flex_child(conflict, 1.0)
+        .padding(15.0)
+}
+
+fn path_row(label: &str, text_box: impl Widget<AppState> + 'static) -> impl Widget<AppState> {
+    Flex::row()
+        .with_child(Label::new(label).fix_width(80.0))
+        .with_flex_child(text_box.expand_width(), 1.0)
+}
+
+fn start_merge(data: &AppState, warnings: &mut Vec<String>) -> anyhow::Result<Merge> {
+    let Some(ours) = &data.editor.movie else { anyhow::bail!("no movie loaded") };
+    let base = read_movie(&data.merge_base, warnings)?;
+    let theirs = read_movie(&data.merge_theirs, warnings)?;
+    merge(&base, ours, &theirs)
+}
+
+fn read_movie(path: &str, warnings: &mut Vec<String>) -> anyhow::Result<M64File> {
+    let conversion = format::read_movie(Path::new(path))?;
+    warnings.extend(conversion.warnings.into_iter().map(|warning| format!("{}: {}", path, warning)));
+    Ok(conversion.movie)
+}
+
+fn summary(merge: &Merge) -> String {
+    let mut summary = format!("{} conflicting ranges", merge.conflicts.len());
+    if !merge.header_conflicts.is_empty() {
+        summary.push_str(&format!(", header fields kept from ours: {}", merge.header_conflicts.join(", ")));
+    }
+    summary
+}
+
+fn resolve(data: &mut AppState, side: Side) {
+    if let Some(merge) = &mut data.merge {
+        Arc::make_mut(merge).resolve(data.merge_index, side);
+        data.merge_status = summary(merge);
+    }
+}
+
+fn describe_conflict(data: &AppState) -> String {
+    let Some(merge) = &data.merge else { return String::new() };
+    let Some(conflict) = merge.conflicts.get(data.merge_index) else { return "No conflicts".to_string() };
+    let resolution = match conflict.resolution {
+        Some(Side::Ours) => "taking ours",
+        Some(Side::Theirs) => "taking theirs",
+        None => "unresolved",
+    };
+    format!(
+        "Conflict {}/{} on controller {}, base frames {}-{} ({})\n\nOurs:\n{}\nTheirs:\n{}",
+        data.merge_index + 1,
+        merge.conflicts.len(),
+        conflict.controller + 1,
+        conflict.base.start,
+        conflict.base.end.saturating_sub(1),
+        resolution,
+        input_text::encode(&conflict.ours),
+        input_text::encode(&conflict.theirs),
+    )
+}
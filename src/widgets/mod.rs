@@ -0,0 +1,9 @@
+pub mod backup_view;
+pub mod diff_view;
+pub mod grid;
+pub mod merge_view;
+pub mod rom_view;
+pub mod script_view;
+pub mod stats_view;
+pub mod stick;
+pub mod tools;
\ No newline at end of file
@@ -0,0 +1,70 @@
+use crate::api::history::Edit;
+use crate::api::rom::Rom;
+use crate::editor::submit_edit;
+use crate::{AppState, SET_ROM_FILE};
+use druid::widget::{Button, CrossAxisAlignment, Flex, Label, LineBreaking, TextBox};
+use druid::{FileDialogOptions, Widget, WidgetExt};
+use druid_shell::FileSpec;
+use std::path::Path;
+
+/// Checks the ROM fields of the movie header against a ROM, or fills them in from it.
+pub fn build_rom_panel() -> impl Widget<AppState> {
+    let rom_spec = FileSpec::new("N64 ROMs", &["z64", "n64", "v64"]);
+    let open_dialog_options = FileDialogOptions::new()
+        .allowed_types(vec![rom_spec])
+        .default_type(rom_spec)
+        .title("Choose the ROM the movie was recorded on")
+        .accept_command(SET_ROM_FILE);
+
+    let path_row = Flex::row()
+        .with_child(Label::new("ROM:"))
+        .with_spacer(10.0)
+        .with_flex_child(TextBox::new().lens(AppState::rom_path).expand_width(), 1.0)
+        .with_spacer(10.0)
+        .with_child(Button::new("...").on_click(move |ctx, _data: &mut AppState, _| {
+            ctx.submit_command(druid::commands::SHOW_OPEN_PANEL.with(open_dialog_options.clone()))
+        }))
+        .with_spacer(10.0)
+        .with_child(Button::new("Check").on_click(|_, data: &mut AppState, _| check_rom(data)))
+        .with_spacer(4.0)
+        .with_child(Button::new("Fill header").on_click(|ctx, data: &mut AppState, _| {
+            let Some(movie) = &data.editor.movie else { return };
+            let mut new = movie.as_ref().clone();
+            match Rom::open(Path::new(&data.rom_path)).and_then(|rom| rom.fill_header(&mut new)) {
+                Ok(()) => {
+                    submit_edit(ctx, Some(Edit::header(movie, &new)));
+                    data.rom_status = "Header filled from the ROM".to_string();
+                }
+                Err(e) => data.rom_status = format!("Failed to read the ROM: {}", e),
+            }
+        }));
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(path_row)
+        .with_spacer(4.0)
+        .with_child(
+            Label::dynamic(|status: &String, _| status.clone())
+                .with_line_break_mode(LineBreaking::WordWrap)
+                .lens(AppState::rom_status),
+        )
+}
+
+/// Compares the movie with the chosen ROM, listing any mismatches in the ROM status.
+pub fn check_rom(data: &mut AppState) {
+    let Some(movie) = &data.editor.movie else { return };
+    if data.rom_path.is_empty() {
+        return;
+    }
+    data.rom_status = match Rom::open(Path::new(&data.rom_path)) {
+        Ok(rom) => {
+            let warnings = rom.check_header(movie);
+            if warnings.is_empty() {
+                format!("Movie matches {} ({:08X})", rom.name, rom.crc1)
+            } else {
+                format!("Warning: {}", warnings.join("\n         "))
+            }
+        }
+        Err(e) => format!("Failed to read the ROM: {}", e),
+    };
+}
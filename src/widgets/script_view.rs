@@ -0,0 +1,58 @@
+use crate::api::history::Edit;
+use crate::api::scripting::run_script;
+use crate::editor::submit_edit;
+use crate::{AppState, SET_SCRIPT_FILE};
+use druid::widget::{Button, CrossAxisAlignment, Flex, Label, LineBreaking, TextBox};
+use druid::{EventCtx, FileDialogOptions, FontDescriptor, FontFamily, Widget, WidgetExt};
+use druid_shell::FileSpec;
+use std::fs;
+
+/// Runs a Rhai script file on the movie being edited, as a single undoable edit.
+pub fn build_script_tab() -> impl Widget<AppState> {
+    let rhai_spec = FileSpec::new("Rhai scripts", &["rhai"]);
+    let open_dialog_options = FileDialogOptions::new()
+        .allowed_types(vec![rhai_spec])
+        .default_type(rhai_spec)
+        .title("Choose a script to run")
+        .accept_command(SET_SCRIPT_FILE);
+
+    let path_row = Flex::row()
+        .with_child(Label::new("Script:"))
+        .with_spacer(10.0)
+        .with_flex_child(TextBox::new().lens(AppState::script_path).expand_width(), 1.0)
+        .with_spacer(10.0)
+        .with_child(Button::new("...").on_click(move |ctx, _data: &mut AppState, _| {
+            ctx.submit_command(druid::commands::SHOW_OPEN_PANEL.with(open_dialog_options.clone()))
+        }))
+        .with_spacer(10.0)
+        .with_child(Button::new("Run").on_click(|ctx, data: &mut AppState, _| {
+            data.script_output = match run(ctx, data) {
+                Ok(output) => output,
+                Err(e) => e.to_string(),
+            };
+        }));
+
+    let output = Label::dynamic(|output: &String, _| output.clone())
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .lens(AppState::script_output)
+        .scroll()
+        .vertical()
+        .expand();
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(path_row)
+        .with_spacer(10.0)
+        .with_flex_child(output, 1.0)
+        .padding(15.0)
+}
+
+fn run(ctx: &mut EventCtx, data: &AppState) -> anyhow::Result<String> {
+    let Some(movie) = &data.editor.movie else { return Ok("No movie loaded".to_string()) };
+    let script = fs::read_to_string(&data.script_path)?;
+    let mut new = movie.as_ref().clone();
+    let output = run_script(&script, &mut new)?;
+    submit_edit(ctx, Some(Edit::replace_movie(movie, &new)));
+    Ok(output)
+}
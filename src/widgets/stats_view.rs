@@ -0,0 +1,59 @@
+use crate::api::stats::stats;
+use crate::{AppState, SAVE_STATS};
+use druid::widget::{Button, CrossAxisAlignment, Flex, Label};
+use druid::{FileDialogOptions, FontDescriptor, FontFamily, Widget, WidgetExt};
+use druid_shell::FileSpec;
+use std::ops::Range;
+
+/// Input statistics of the current controller, for the selection or the whole movie.
+pub fn build_stats_tab() -> impl Widget<AppState> {
+    let json_spec = FileSpec::new("JSON files", &["json"]);
+    let save_dialog_options = FileDialogOptions::new()
+        .allowed_types(vec![json_spec])
+        .default_type(json_spec)
+        .default_name("stats.json".to_string())
+        .title("Export statistics")
+        .button_text("Export")
+        .accept_command(SAVE_STATS);
+
+    let button_row = Flex::row()
+        .with_child(Button::new("Selection").on_click(|_, data: &mut AppState, _| {
+            let range = data.editor.selection();
+            show_stats(data, range);
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("Movie").on_click(|_, data: &mut AppState, _| {
+            let range = 0..data.editor.inputs().len();
+            show_stats(data, range);
+        }))
+        .with_spacer(10.0)
+        .with_child(Button::new("Export JSON").on_click(move |ctx, data: &mut AppState, _| {
+            if !data.stats_json.is_empty() {
+                ctx.submit_command(druid::commands::SHOW_SAVE_PANEL.with(save_dialog_options.clone()))
+            }
+        }));
+
+    let output = Label::dynamic(|output: &String, _| output.clone())
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+        .lens(AppState::stats_output)
+        .scroll()
+        .vertical()
+        .expand();
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(button_row)
+        .with_spacer(10.0)
+        .with_flex_child(output, 1.0)
+        .padding(15.0)
+}
+
+fn show_stats(data: &mut AppState, range: Range<usize>) {
+    if data.editor.movie.is_none() {
+        data.stats_output = "No movie loaded".to_string();
+        return;
+    }
+    let stats = stats(data.editor.inputs(), range);
+    data.stats_output = format!("Controller {}\n{}", data.editor.controller + 1, stats);
+    data.stats_json = stats.to_json();
+}
@@ -0,0 +1,137 @@
+use crate::api::patterns;
+use crate::api::stick::{self, SnapMode, STICK_MAX};
+use crate::editor::{submit_edit, EditorState};
+use druid::kurbo::{Circle, Line};
+use druid::piet::{FontFamily, Text, TextLayoutBuilder};
+use druid::widget::prelude::*;
+use druid::{Color, MouseButton, Point};
+
+const PAD_SIZE: f64 = 220.0;
+const READOUT_HEIGHT: f64 = 40.0;
+const MARGIN: f64 = 10.0;
+
+/// Circular joystick editor for the stick position of the selected frames.
+///
+/// Dragging inside the pad previews a position, which is written to every selected frame
+/// when the mouse is released. Right-click cycles through the snap modes.
+pub struct StickEditor {
+    snap: SnapMode,
+    drag: Option<(i8, i8)>,
+}
+
+impl StickEditor {
+    pub fn new() -> StickEditor {
+        StickEditor {
+            snap: SnapMode::Free,
+            drag: None,
+        }
+    }
+
+    fn center() -> Point {
+        Point::new(PAD_SIZE / 2.0, PAD_SIZE / 2.0)
+    }
+
+    fn radius() -> f64 {
+        PAD_SIZE / 2.0 - MARGIN
+    }
+
+    fn to_stick(&self, pos: Point) -> (i8, i8) {
+        let center = Self::center();
+        let scale = STICK_MAX / Self::radius();
+        stick::snap((pos.x - center.x) * scale, (center.y - pos.y) * scale, self.snap)
+    }
+
+    fn to_screen(x: i8, y: i8) -> Point {
+        let center = Self::center();
+        let scale = Self::radius() / STICK_MAX;
+        Point::new(center.x + x as f64 * scale, center.y - y as f64 * scale)
+    }
+
+    fn position(&self, data: &EditorState) -> Option<(i8, i8)> {
+        self.drag.or_else(|| data.input_at(data.cursor).map(|input| (input.x, input.y)))
+    }
+}
+
+impl Widget<EditorState> for StickEditor {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut EditorState, _env: &Env) {
+        match event {
+            Event::MouseDown(mouse) if mouse.button == MouseButton::Right => {
+                self.snap = self.snap.next();
+                ctx.request_paint();
+            }
+            Event::MouseDown(mouse) if mouse.pos.y < PAD_SIZE && data.movie.is_some() => {
+                ctx.set_active(true);
+                self.drag = Some(self.to_stick(mouse.pos));
+                ctx.request_paint();
+            }
+            Event::MouseMove(mouse) if ctx.is_active() => {
+                self.drag = Some(self.to_stick(mouse.pos));
+                ctx.request_paint();
+            }
+            Event::MouseUp(_) if ctx.is_active() => {
+                ctx.set_active(false);
+                let edit = self.drag.take().and_then(|(x, y)| data.edit_selection(|inputs| patterns::set_stick(inputs, x, y)));
+                submit_edit(ctx, edit);
+                ctx.request_paint();
+            }
+            _ => {}
+        }
+    }
+
+    fn lifecycle(&mut self, _ctx: &mut LifeCycleCtx, _event: &LifeCycle, _data: &EditorState, _env: &Env) {}
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &EditorState, data: &EditorState, _env: &Env) {
+        if !old_data.same(data) {
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(&mut self, _ctx: &mut LayoutCtx, bc: &BoxConstraints, _data: &EditorState, _env: &Env) -> Size {
+        bc.constrain(Size::new(PAD_SIZE, PAD_SIZE + READOUT_HEIGHT))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &EditorState, _env: &Env) {
+        let center = Self::center();
+        let radius = Self::radius();
+        let guide = Color::grey8(0x50);
+        ctx.fill(Circle::new(center, radius), &Color::grey8(0x20));
+        ctx.stroke(Circle::new(center, radius), &guide, 1.0);
+        for m in SnapMode::MAGNITUDES {
+            ctx.stroke(Circle::new(center, m / STICK_MAX * radius), &guide, 0.5);
+        }
+        for i in 0..8 {
+            let angle = i as f64 * std::f64::consts::FRAC_PI_4;
+            let end = Point::new(center.x + angle.cos() * radius, center.y - angle.sin() * radius);
+            ctx.stroke(Line::new(center, end), &guide, if i % 2 == 0 { 1.0 } else { 0.5 });
+        }
+
+        // Previous and next frames as ghost points
+        let ghosts = [
+            data.cursor.checked_sub(1).and_then(|frame| data.input_at(frame)),
+            data.input_at(data.cursor + 1),
+        ];
+        for input in ghosts.into_iter().flatten() {
+            ctx.fill(Circle::new(Self::to_screen(input.x, input.y), 4.0), &Color::rgba8(0x80, 0x80, 0xFF, 0x80));
+        }
+
+        let readout = match self.position(data) {
+            Some((x, y)) => {
+                let point = Self::to_screen(x, y);
+                ctx.stroke(Line::new(center, point), &Color::rgb8(0xFF, 0x60, 0x60), 1.5);
+                ctx.fill(Circle::new(point, 5.0), &Color::rgb8(0xFF, 0x60, 0x60));
+                format!(
+                    "X: {:4}  Y: {:4}  Mag: {:6.2}  Ang: {:6.2}\nSnap: {} (right-click to change)",
+                    x, y, stick::magnitude(x, y), stick::angle(x, y), self.snap
+                )
+            }
+            None => format!("No movie loaded\nSnap: {} (right-click to change)", self.snap),
+        };
+        let layout = ctx.text()
+            .new_text_layout(readout)
+            .font(FontFamily::MONOSPACE, 11.0)
+            .text_color(Color::WHITE)
+            .build()
+            .unwrap();
+        ctx.draw_text(&layout, (MARGIN, PAD_SIZE + 4.0));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::api::expression::Expression;
+use crate::api::history::Edit;
+use crate::api::m64_handling::{Button as InputButton, Input};
+use crate::api::patterns;
+use crate::api::search::{self, Predicate};
+use crate::api::stick::{self, Gate};
+use crate::editor::{submit_edit, EditorState};
+use crate::widgets::grid::InputGrid;
+use druid::widget::{Button, CrossAxisAlignment, Flex, Label, TextBox};
+use druid::{EventCtx, Widget, WidgetExt};
+use std::ops::Range;
+
+/// Range tools applied to the selected frames of the current controller.
+pub fn build_tools() -> impl Widget<EditorState> {
+    let button_row = Flex::row()
+        .with_child(Label::new("Button:"))
+        .with_spacer(4.0)
+        .with_child(TextBox::new().lens(EditorState::tool_button).fix_width(40.0))
+        .with_spacer(8.0)
+        .with_child(Label::new("Every:"))
+        .with_spacer(4.0)
+        .with_child(TextBox::new().lens(EditorState::tool_period).fix_width(40.0));
+
+    let button_tools = Flex::row()
+        .with_child(Button::new("Hold").on_click(|ctx, data: &mut EditorState, _| {
+            with_button(ctx, data, |inputs, button| patterns::hold(inputs, button))
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("Release").on_click(|ctx, data: &mut EditorState, _| {
+            with_button(ctx, data, |inputs, button| patterns::release(inputs, button))
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("Mash").on_click(|ctx, data: &mut EditorState, _| {
+            let Ok(period) = data.tool_period.trim().parse::<usize>() else {
+                data.tool_status = format!("Invalid period \"{}\"", data.tool_period);
+                return;
+            };
+            with_button(ctx, data, |inputs, button| patterns::mash(inputs, button, period))
+        }));
+
+    let range_tools = Flex::row()
+        .with_child(Button::new("Interpolate stick").on_click(|ctx, data: &mut EditorState, _| {
+            submit_edit(ctx, data.edit_selection(patterns::interpolate_keyframes))
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("Repeat clipboard").on_click(|ctx, data: &mut EditorState, _| {
+            if let Some(pattern) = InputGrid::clipboard_inputs(data) {
+                submit_edit(ctx, data.edit_selection(|inputs| patterns::repeat(inputs, &pattern)))
+            }
+        }));
+
+    let value_row = Flex::row()
+        .with_child(Label::new("Value:"))
+        .with_spacer(4.0)
+        .with_child(TextBox::new().lens(EditorState::tool_value).fix_width(60.0))
+        .with_spacer(4.0)
+        .with_child(Button::new("Mirror X").on_click(|ctx, data: &mut EditorState, _| {
+            submit_edit(ctx, data.edit_selection(stick::mirror_x))
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("Mirror Y").on_click(|ctx, data: &mut EditorState, _| {
+            submit_edit(ctx, data.edit_selection(stick::mirror_y))
+        }));
+
+    let stick_tools = Flex::row()
+        .with_child(Button::new("Rotate").on_click(|ctx, data: &mut EditorState, _| {
+            with_value(ctx, data, stick::rotate)
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("Scale").on_click(|ctx, data: &mut EditorState, _| {
+            with_value(ctx, data, stick::scale)
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("Circle").on_click(|ctx, data: &mut EditorState, _| {
+            with_value(ctx, data, |inputs, radius| stick::clamp_to_gate(inputs, Gate::Circle(radius)))
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("Octagon").on_click(|ctx, data: &mut EditorState, _| {
+            with_value(ctx, data, |inputs, radius| stick::clamp_to_gate(inputs, Gate::Octagon(radius)))
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("Deadzone").on_click(|ctx, data: &mut EditorState, _| {
+            with_value(ctx, data, stick::remap_deadzone)
+        }));
+
+    let model_row = Flex::row()
+        .with_child(
+            Button::dynamic(|data: &EditorState, _| format!("Model: {}", data.stick_model.name()))
+                .on_click(|_, data: &mut EditorState, _| {
+                    let models = stick::models();
+                    let current = models.iter().position(|model| model.name() == data.stick_model.name()).unwrap_or(0);
+                    data.stick_model = models[(current + 1) % models.len()].clone();
+                }),
+        )
+        .with_spacer(4.0)
+        .with_child(Button::new("Normalise").on_click(|ctx, data: &mut EditorState, _| {
+            let model = data.stick_model.clone();
+            submit_edit(ctx, data.edit_selection(|inputs| stick::normalise(inputs, model.as_ref())))
+        }));
+
+    let mut port_row = Flex::row().with_child(Label::new("Port:"));
+    for port in 0..4 {
+        port_row.add_spacer(4.0);
+        port_row.add_child(Button::new(format!("{}", port + 1)).on_click(move |_, data: &mut EditorState, _| {
+            data.controller = port;
+            data.clamp_selection();
+        }));
+    }
+    port_row.add_spacer(8.0);
+    port_row.add_child(Button::new("Swap with value").on_click(|ctx, data: &mut EditorState, _| {
+        // Ports are numbered from 1 in the editor
+        match data.tool_value.trim().parse::<usize>() {
+            Ok(port @ 1..=4) if data.movie.is_some() => {
+                submit_edit(ctx, Some(Edit::SwapPorts(data.controller, port - 1)));
+                data.controller = port - 1;
+            }
+            _ => data.tool_status = format!("Invalid port \"{}\"", data.tool_value),
+        }
+    }));
+
+    let search_row = Flex::row()
+        .with_child(Label::new("Find:"))
+        .with_spacer(4.0)
+        .with_child(TextBox::new().with_placeholder("A && !B").lens(EditorState::search).fix_width(150.0))
+        .with_spacer(4.0)
+        .with_child(Button::new("First").on_click(|_, data: &mut EditorState, _| {
+            find(data, |inputs, predicate, _| search::first(inputs, predicate))
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("<").on_click(|_, data: &mut EditorState, _| find(data, search::previous)))
+        .with_spacer(4.0)
+        .with_child(Button::new(">").on_click(|_, data: &mut EditorState, _| find(data, search::next)));
+
+    let expression_row = Flex::row()
+        .with_child(Label::new("Apply:"))
+        .with_spacer(4.0)
+        .with_child(
+            TextBox::new()
+                .with_placeholder("where A set B = true, x = -x")
+                .lens(EditorState::expression)
+                .fix_width(190.0),
+        )
+        .with_spacer(4.0)
+        .with_child(Button::new("Selection").on_click(|ctx, data: &mut EditorState, _| {
+            let range = data.selection();
+            apply_expression(ctx, data, range)
+        }))
+        .with_spacer(4.0)
+        .with_child(Button::new("All").on_click(|ctx, data: &mut EditorState, _| {
+            let range = 0..data.inputs().len();
+            apply_expression(ctx, data, range)
+        }));
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(port_row)
+        .with_spacer(10.0)
+        .with_child(search_row)
+        .with_spacer(4.0)
+        .with_child(expression_row)
+        .with_child(Label::dynamic(|status: &String, _| status.clone()).lens(EditorState::tool_status))
+        .with_spacer(10.0)
+        .with_child(Label::new("Selection tools"))
+        .with_spacer(4.0)
+        .with_child(button_row)
+        .with_spacer(4.0)
+        .with_child(button_tools)
+        .with_spacer(4.0)
+        .with_child(range_tools)
+        .with_spacer(10.0)
+        .with_child(Label::new("Stick transforms"))
+        .with_spacer(4.0)
+        .with_child(value_row)
+        .with_spacer(4.0)
+        .with_child(stick_tools)
+        .with_spacer(4.0)
+        .with_child(model_row)
+}
+
+fn with_button(ctx: &mut EventCtx, data: &mut EditorState, f: impl FnOnce(&mut [Input], InputButton)) {
+    match data.tool_button.trim().parse::<InputButton>() {
+        Ok(button) => submit_edit(ctx, data.edit_selection(|inputs| f(inputs, button))),
+        Err(e) => data.tool_status = e.to_string(),
+    }
+}
+
+fn with_value(ctx: &mut EventCtx, data: &mut EditorState, f: impl FnOnce(&mut [Input], f64)) {
+    match data.tool_value.trim().parse::<f64>() {
+        Ok(value) => submit_edit(ctx, data.edit_selection(|inputs| f(inputs, value))),
+        Err(_) => data.tool_status = format!("Invalid value \"{}\"", data.tool_value),
+    }
+}
+
+/// Moves the cursor to the frame found by `f` for the search predicate.
+fn find(data: &mut EditorState, f: impl FnOnce(&[Input], &Predicate, usize) -> Option<usize>) {
+    let predicate = match data.search.parse::<Predicate>() {
+        Ok(predicate) => predicate,
+        Err(e) => {
+            data.tool_status = e.to_string();
+            return;
+        }
+    };
+    let ranges = search::find(data.inputs(), &predicate);
+    match f(data.inputs(), &predicate, data.cursor) {
+        Some(frame) => {
+            let index = ranges.iter().position(|range| range.contains(&frame)).unwrap_or(0);
+            data.tool_status = format!("Match {} of {}", index + 1, ranges.len());
+            data.select(frame, false);
+        }
+        None => data.tool_status = format!("No more matches ({} in total)", ranges.len()),
+    }
+}
+
+fn apply_expression(ctx: &mut EventCtx, data: &mut EditorState, range: Range<usize>) {
+    let expression = match data.expression.parse::<Expression>() {
+        Ok(expression) => expression,
+        Err(e) => {
+            data.tool_status = e.to_string();
+            return;
+        }
+    };
+    let mut changed = 0;
+    let edit = data.edit_range(range, |inputs| changed = expression.apply(inputs));
+    submit_edit(ctx, edit);
+    data.tool_status = format!("Changed {} frames", changed);
+}